@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+/// Assembles source written in the notation produced by `disasm`
+/// (Cowgod's mnemonics) into a program loaded at `start`.
+///
+/// Besides the instructions it understands:
+///```
+/// loop:           ; labels, usable wherever an address or byte is expected
+///     JP loop
+///     DB 0xF0, 144 ; raw bytes (DW for 16-bit words)
+///```
+/// Numbers may be decimal, `0x`/`#` hexadecimal or `0b` binary.
+pub fn assemble(source: &str, start: u16) -> Result<Vec<u8>, String> {
+    let lines = parse(source)?;
+
+    // first pass: find where every label points to
+    let mut labels = HashMap::new();
+    let mut address = start as usize;
+    for line in &lines {
+        for label in &line.labels {
+            if labels.insert(label.clone(), address as u16).is_some() {
+                return Err(format!("line {}: label `{}` defined twice", line.number, label));
+            }
+        }
+        if let Some((mnemonic, operands)) = &line.instruction {
+            address += size(mnemonic, operands);
+        }
+    }
+
+    // second pass: encode with every label resolved
+    let mut program = Vec::new();
+    for line in &lines {
+        if let Some((mnemonic, operands)) = &line.instruction {
+            let bytes = encode(mnemonic, operands, &labels)
+                .map_err(|error| format!("line {}: {}", line.number, error))?;
+            program.extend(bytes);
+        }
+    }
+
+    Ok(program)
+}
+
+struct Line {
+    number: usize,
+    labels: Vec<String>,
+    instruction: Option<(String, Vec<String>)>,
+}
+
+fn parse(source: &str) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+
+    for (n, text) in source.lines().enumerate() {
+        let mut text = text.split(';').next().unwrap_or("").trim();
+        let mut labels = Vec::new();
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) {
+                return Err(format!("line {}: invalid label `{}`", n + 1, label));
+            }
+            labels.push(label.to_string());
+            text = text[colon + 1..].trim();
+        }
+
+        let instruction = if text.is_empty() {
+            None
+        } else {
+            let mut parts = text.splitn(2, char::is_whitespace);
+            let mnemonic = parts.next().unwrap_or("").to_ascii_uppercase();
+            let operands = parts
+                .next()
+                .map(|operands| operands.split(',').map(|operand| operand.trim().to_string()).collect())
+                .unwrap_or_default();
+            Some((mnemonic, operands))
+        };
+
+        lines.push(Line {
+            number: n + 1,
+            labels,
+            instruction,
+        });
+    }
+
+    Ok(lines)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn size(mnemonic: &str, operands: &[String]) -> usize {
+    match mnemonic {
+        "DB" => operands.len(),
+        "DW" => operands.len() * 2,
+        _ => 2,
+    }
+}
+
+fn number(operand: &str) -> Option<u32> {
+    let lower = operand.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn value(operand: &str, max: u32, labels: &HashMap<String, u16>) -> Result<u16, String> {
+    let value = match number(operand) {
+        Some(value) => value,
+        None => *labels
+            .get(operand)
+            .ok_or_else(|| format!("unknown value or label `{}`", operand))? as u32,
+    };
+
+    if value > max {
+        return Err(format!("`{}` doesn't fit in 0x{:X}", operand, max));
+    }
+    Ok(value as u16)
+}
+
+fn register(operand: &str) -> Option<u16> {
+    let mut chars = operand.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(x), None) | (Some('v'), Some(x), None) => x.to_digit(16).map(|x| x as u16),
+        _ => None,
+    }
+}
+
+fn encode(mnemonic: &str, operands: &[String], labels: &HashMap<String, u16>) -> Result<Vec<u8>, String> {
+    if mnemonic == "DB" {
+        return operands.iter().map(|operand| value(operand, 0xFF, labels).map(|byte| byte as u8)).collect();
+    }
+    if mnemonic == "DW" {
+        let mut bytes = Vec::new();
+        for operand in operands {
+            let word = value(operand, 0xFFFF, labels)?;
+            bytes.push((word >> 8) as u8);
+            bytes.push(word as u8);
+        }
+        return Ok(bytes);
+    }
+
+    let addr = |operand: &String| value(operand, 0xFFF, labels);
+    let byte = |operand: &String| value(operand, 0xFF, labels);
+    let upper: Vec<String> = operands.iter().map(|operand| operand.to_ascii_uppercase()).collect();
+    let ops: Vec<&str> = upper.iter().map(String::as_str).collect();
+    let reg = |n: usize| register(ops[n]);
+
+    let opcode = match (mnemonic, ops.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [_]) => addr(&operands[0])?,
+        ("JP", ["V0", _]) => 0xB000 | addr(&operands[1])?,
+        ("JP", [_]) => 0x1000 | addr(&operands[0])?,
+        ("CALL", [_]) => 0x2000 | addr(&operands[0])?,
+        ("SE", [_, _]) | ("SNE", [_, _]) => {
+            let x = reg(0).ok_or("expected a register")?;
+            let (register_form, byte_form) = if mnemonic == "SE" { (0x5000, 0x3000) } else { (0x9000, 0x4000) };
+            match reg(1) {
+                Some(y) => register_form | x << 8 | y << 4,
+                None => byte_form | x << 8 | byte(&operands[1])?,
+            }
+        }
+        ("PLANE", [_]) => 0xF001 | value(&operands[0], 0x3, labels)? << 8,
+        ("LD", ["I", _]) => 0xA000 | addr(&operands[1])?,
+        ("LD", ["DT", _]) => 0xF015 | reg(1).ok_or("expected a register")? << 8,
+        ("LD", ["ST", _]) => 0xF018 | reg(1).ok_or("expected a register")? << 8,
+        ("LD", ["F", _]) => 0xF029 | reg(1).ok_or("expected a register")? << 8,
+        ("LD", ["HF", _]) => 0xF030 | reg(1).ok_or("expected a register")? << 8,
+        ("LD", ["B", _]) => 0xF033 | reg(1).ok_or("expected a register")? << 8,
+        ("LD", ["[I]", _]) => 0xF055 | reg(1).ok_or("expected a register")? << 8,
+        ("LD", [_, "DT"]) => 0xF007 | reg(0).ok_or("expected a register")? << 8,
+        ("LD", [_, "K"]) => 0xF00A | reg(0).ok_or("expected a register")? << 8,
+        ("LD", [_, "[I]"]) => 0xF065 | reg(0).ok_or("expected a register")? << 8,
+        ("LD", [_, _]) => {
+            let x = reg(0).ok_or("expected a register")?;
+            match reg(1) {
+                Some(y) => 0x8000 | x << 8 | y << 4,
+                None => 0x6000 | x << 8 | byte(&operands[1])?,
+            }
+        }
+        ("ADD", ["I", _]) => 0xF01E | reg(1).ok_or("expected a register")? << 8,
+        ("ADD", [_, _]) => {
+            let x = reg(0).ok_or("expected a register")?;
+            match reg(1) {
+                Some(y) => 0x8004 | x << 8 | y << 4,
+                None => 0x7000 | x << 8 | byte(&operands[1])?,
+            }
+        }
+        ("OR", [_, _]) | ("AND", [_, _]) | ("XOR", [_, _]) | ("SUB", [_, _]) | ("SUBN", [_, _]) => {
+            let n = match mnemonic {
+                "OR" => 0x1,
+                "AND" => 0x2,
+                "XOR" => 0x3,
+                "SUB" => 0x5,
+                _ => 0x7,
+            };
+            let x = reg(0).ok_or("expected a register")?;
+            let y = reg(1).ok_or("expected a register")?;
+            0x8000 | x << 8 | y << 4 | n
+        }
+        ("SHR", [_]) | ("SHR", [_, _]) | ("SHL", [_]) | ("SHL", [_, _]) => {
+            let n = if mnemonic == "SHR" { 0x6 } else { 0xE };
+            let x = reg(0).ok_or("expected a register")?;
+            let y = if ops.len() == 2 { reg(1).ok_or("expected a register")? } else { x };
+            0x8000 | x << 8 | y << 4 | n
+        }
+        ("RND", [_, _]) => 0xC000 | reg(0).ok_or("expected a register")? << 8 | byte(&operands[1])?,
+        ("DRW", [_, _, _]) => {
+            let x = reg(0).ok_or("expected a register")?;
+            let y = reg(1).ok_or("expected a register")?;
+            0xD000 | x << 8 | y << 4 | value(&operands[2], 0xF, labels)?
+        }
+        ("SKP", [_]) => 0xE09E | reg(0).ok_or("expected a register")? << 8,
+        ("SKNP", [_]) => 0xE0A1 | reg(0).ok_or("expected a register")? << 8,
+        _ => return Err(format!("unknown instruction `{} {}`", mnemonic, operands.join(", "))),
+    };
+
+    Ok(vec![(opcode >> 8) as u8, opcode as u8])
+}
@@ -0,0 +1,305 @@
+/// What the CPU is wired to: the memory and the I/O lines of the peripherals.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    /// `OUT 1` to `OUT 7`, with the byte put on the bus.
+    fn output(&mut self, port: u8, value: u8);
+    /// `INP 1` to `INP 7`, the byte the peripheral puts on the bus.
+    fn input(&mut self, port: u8) -> u8;
+    /// Whether `EF1` to `EF4` is asserted, as the branch instructions see it.
+    fn flag(&self, flag: u8) -> bool;
+}
+
+/// **RCA CDP1802**, the CPU of the COSMAC VIP.
+/// 16 registers of 16 bits, any of which can be the program counter (`P`)
+/// or the pointer into memory of the ALU instructions (`X`).
+/// Every instruction takes 2 machine cycles, long branches and skips 3,
+/// and a machine cycle is 8 clock cycles.
+pub struct Cdp1802 {
+    pub r: [u16; 16],
+    pub p: u8,
+    pub x: u8,
+    pub d: u8,
+    pub df: bool,
+    /// `X` and `P` saved by interrupts.
+    pub t: u8,
+    /// Interrupts enabled.
+    pub ie: bool,
+    /// The output flip-flop, which drives the speaker of the VIP.
+    pub q: bool,
+    /// Stopped by `IDL` until the next interrupt or DMA.
+    idle: bool,
+}
+
+impl Cdp1802 {
+    /// The state after a reset: running from `R0`, with interrupts enabled.
+    pub fn new() -> Self {
+        Self {
+            r: [0; 16],
+            p: 0,
+            x: 0,
+            d: 0,
+            df: false,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    /// Runs one instruction and returns the machine cycles it took,
+    /// or waits a cycle if idle.
+    pub fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        if self.idle {
+            return 1;
+        }
+
+        let opcode = self.fetch(bus);
+        let (i, n) = (opcode >> 4, opcode & 0xF);
+        let rx = self.r[self.x as usize];
+
+        match i {
+            0x0 if n == 0 => self.idle = true, // IDL
+            0x0 => self.d = bus.read(self.r[n as usize]), // LDN
+            0x1 => self.r[n as usize] = self.r[n as usize].wrapping_add(1), // INC
+            0x2 => self.r[n as usize] = self.r[n as usize].wrapping_sub(1), // DEC
+            0x3 => {
+                // short branches, to an address in the current page
+                let taken = match n & 0x7 {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    flag => bus.flag(flag - 3),
+                };
+                // the second half are the negated ones, `38` skips the next byte
+                if taken != (n >= 0x8) {
+                    let target = bus.read(self.r[self.p as usize]);
+                    let pc = &mut self.r[self.p as usize];
+                    *pc = (*pc & 0xFF00) | target as u16;
+                } else {
+                    self.advance();
+                }
+            }
+            0x4 => {
+                // LDA
+                self.d = bus.read(self.r[n as usize]);
+                self.r[n as usize] = self.r[n as usize].wrapping_add(1);
+            }
+            0x5 => bus.write(self.r[n as usize], self.d), // STR
+            0x6 => match n {
+                0x0 => self.r[self.x as usize] = rx.wrapping_add(1), // IRX
+                0x1..=0x7 => {
+                    // OUT
+                    let value = bus.read(rx);
+                    bus.output(n, value);
+                    self.r[self.x as usize] = rx.wrapping_add(1);
+                }
+                0x8 => (), // not used by the 1802
+                _ => {
+                    // INP
+                    self.d = bus.input(n - 8);
+                    bus.write(rx, self.d);
+                }
+            },
+            0x7 => self.execute_7(n, bus),
+            0x8 => self.d = self.r[n as usize] as u8, // GLO
+            0x9 => self.d = (self.r[n as usize] >> 8) as u8, // GHI
+            0xA => self.r[n as usize] = (self.r[n as usize] & 0xFF00) | self.d as u16, // PLO
+            0xB => self.r[n as usize] = (self.r[n as usize] & 0x00FF) | (self.d as u16) << 8, // PHI
+            0xC => {
+                // long branches and skips
+                let condition = match n & 0x3 {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    _ => self.df,
+                };
+                match n {
+                    // NOP
+                    0x4 => (),
+                    // LSIE
+                    0xC => self.skip(self.ie),
+                    // LSNQ, LSNZ, LSNF, LSKP
+                    0x5..=0x8 => self.skip(!condition || n == 0x8),
+                    // LSQ, LSZ, LSDF
+                    0xD..=0xF => self.skip(condition),
+                    // LBR, LBQ, LBZ, LBDF, then the negated ones
+                    _ => {
+                        if condition != (n >= 0x8) {
+                            let pc = self.r[self.p as usize];
+                            let high = bus.read(pc) as u16;
+                            let low = bus.read(pc.wrapping_add(1)) as u16;
+                            self.r[self.p as usize] = high << 8 | low;
+                        } else {
+                            self.skip(true);
+                        }
+                    }
+                }
+                return 3;
+            }
+            0xD => self.p = n,  // SEP
+            0xE => self.x = n,  // SEX
+            _ => self.execute_f(n, bus),
+        }
+        2
+    }
+
+    fn execute_7(&mut self, n: u8, bus: &mut impl Bus) {
+        let rx = self.r[self.x as usize];
+        match n {
+            0x0 | 0x1 => {
+                // RET, DIS
+                let value = bus.read(rx);
+                self.r[self.x as usize] = rx.wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0x0;
+            }
+            0x2 => {
+                // LDXA
+                self.d = bus.read(rx);
+                self.r[self.x as usize] = rx.wrapping_add(1);
+            }
+            0x3 => {
+                // STXD
+                bus.write(rx, self.d);
+                self.r[self.x as usize] = rx.wrapping_sub(1);
+            }
+            0x4 => self.add(bus.read(rx), self.df), // ADC
+            0x5 => self.subtract(bus.read(rx), self.d, self.df), // SDB
+            0x6 => {
+                // SHRC
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            0x7 => self.subtract(self.d, bus.read(rx), self.df), // SMB
+            0x8 => bus.write(rx, self.t), // SAV
+            0x9 => {
+                // MARK
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            0xA => self.q = false, // REQ
+            0xB => self.q = true,  // SEQ
+            0xC => {
+                // ADCI
+                let value = self.immediate(bus);
+                self.add(value, self.df);
+            }
+            0xD => {
+                // SDBI
+                let value = self.immediate(bus);
+                self.subtract(value, self.d, self.df);
+            }
+            0xE => {
+                // SHLC
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+            _ => {
+                // SMBI
+                let value = self.immediate(bus);
+                self.subtract(self.d, value, self.df);
+            }
+        }
+    }
+
+    fn execute_f(&mut self, n: u8, bus: &mut impl Bus) {
+        match n {
+            0x6 => {
+                // SHR
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+                return;
+            }
+            0xE => {
+                // SHL
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+                return;
+            }
+            _ => (),
+        }
+
+        // `F8` to `FF` are the immediate versions of `F0` to `F7`, with `M(R(P))`
+        let value = if n >= 0x8 {
+            self.immediate(bus)
+        } else {
+            bus.read(self.r[self.x as usize])
+        };
+        match n & 0x7 {
+            0x0 => self.d = value,                      // LDX, LDI
+            0x1 => self.d |= value,                     // OR, ORI
+            0x2 => self.d &= value,                     // AND, ANI
+            0x3 => self.d ^= value,                     // XOR, XRI
+            0x4 => self.add(value, false),              // ADD, ADI
+            0x5 => self.subtract(value, self.d, true),  // SD, SDI
+            _ => self.subtract(self.d, value, true),    // SM, SMI
+        }
+    }
+
+    /// Takes an interrupt if they are enabled: saves `X` and `P` in `T`,
+    /// then runs from `R1` with `R2` as `X`. Returns whether it was taken.
+    pub fn interrupt(&mut self) -> bool {
+        if !self.ie {
+            return false;
+        }
+        self.t = self.x << 4 | self.p;
+        self.x = 2;
+        self.p = 1;
+        self.ie = false;
+        self.idle = false;
+        true
+    }
+
+    /// One DMA out cycle: the byte at `R0` goes to the peripheral.
+    pub fn dma_out(&mut self, bus: &mut impl Bus) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let value = bus.read(self.r[self.p as usize]);
+        self.advance();
+        value
+    }
+
+    /// The byte after the instruction, `M(R(P))`.
+    fn immediate(&mut self, bus: &mut impl Bus) -> u8 {
+        self.fetch(bus)
+    }
+
+    fn advance(&mut self) {
+        let pc = &mut self.r[self.p as usize];
+        *pc = pc.wrapping_add(1);
+    }
+
+    /// Skips the 2 bytes after a long skip or a branch not taken.
+    fn skip(&mut self, skip: bool) {
+        if skip {
+            let pc = &mut self.r[self.p as usize];
+            *pc = pc.wrapping_add(2);
+        }
+    }
+
+    fn add(&mut self, value: u8, carry: bool) {
+        let sum = self.d as u16 + value as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    /// `D = from - value`, with `DF` set when there is no borrow.
+    /// `no_borrow` is the `DF` the subtraction starts with.
+    fn subtract(&mut self, from: u8, value: u8, no_borrow: bool) {
+        let difference = from as i16 - value as i16 - !no_borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
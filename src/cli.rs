@@ -0,0 +1,227 @@
+use std::path::PathBuf;
+
+use clap::builder::PossibleValuesParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::config::ColorConfig;
+use crate::cpu::{DisplayMode, Level, MachineCode, Platform, Quirks, Timing};
+use crate::font::Font;
+use crate::graphics::{self, Scaling};
+use crate::palette::Palette;
+
+/// CHIP-8 Emulator built in Rust.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file to use instead of the one in the user config directory
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs a ROM
+    Run(Box<RunOptions>),
+    /// Disassembles a ROM
+    Disasm {
+        rom: PathBuf,
+        /// Address the ROM is loaded at
+        #[arg(long, default_value = "0x200", value_parser = parse_address)]
+        start: u16,
+        /// Prints plain source for `asm`, without addresses and raw words
+        #[arg(long)]
+        source: bool,
+    },
+    /// Assembles a source file into a ROM
+    Asm {
+        source: PathBuf,
+        /// Defaults to the source path with a `.ch8` extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Address the ROM will be loaded at
+        #[arg(long, default_value = "0x200", value_parser = parse_address)]
+        start: u16,
+    },
+    /// Shows information about a ROM
+    Info {
+        rom: PathBuf,
+        /// The computer the ROM was written for, which sets its start and the memory it fits in
+        #[arg(long, default_value = "chip8", value_parser = PossibleValuesParser::new(Platform::NAMES))]
+        platform: String,
+        /// Address the ROM is loaded at, 0x200 unless the platform says otherwise
+        #[arg(long, value_parser = parse_address)]
+        start: Option<u16>,
+    },
+}
+
+/// What runs the ROM.
+#[derive(Copy, Clone, PartialEq, ValueEnum)]
+pub enum MachineType {
+    /// Interprets CHIP-8 instructions directly
+    Chip8,
+    /// A COSMAC VIP running the original interpreter, given with `--interpreter`
+    Vip,
+}
+
+/// Where the display goes.
+#[derive(Copy, Clone, PartialEq, ValueEnum)]
+pub enum Video {
+    /// An OpenGL window
+    Window,
+    /// Unicode half blocks with ANSI colours
+    Terminal,
+    /// One PNG file per frame, inside `--output`
+    Png,
+    /// Nowhere, the last frame is printed when done
+    None,
+}
+
+#[derive(Args)]
+pub struct RunOptions {
+    pub rom: PathBuf,
+    /// What runs the ROM
+    #[arg(long, value_enum, default_value_t = MachineType::Chip8)]
+    pub machine: MachineType,
+    /// The computer the game was written for, which sets where it starts, the memory,
+    /// the display, the font and the quirks
+    #[arg(long, value_parser = PossibleValuesParser::new(Platform::NAMES))]
+    pub platform: Option<String>,
+    /// The original 512-byte CHIP-8 interpreter, for `--machine vip`
+    #[arg(long)]
+    pub interpreter: Option<PathBuf>,
+    /// The VIP monitor ROM, which the interpreter waits for keys with
+    #[arg(long)]
+    pub monitor: Option<PathBuf>,
+    /// Instructions executed per frame, at 60 frames per second
+    #[arg(long)]
+    pub speed: Option<u32>,
+    /// Quirk preset
+    #[arg(long, value_parser = PossibleValuesParser::new(Quirks::PRESETS))]
+    pub quirks: Option<String>,
+    /// Instructions per frame: `fixed` runs `--speed` of them, `vip` as many as the COSMAC VIP did
+    #[arg(long, value_parser = PossibleValuesParser::new(Timing::NAMES))]
+    pub timing: Option<String>,
+    /// What `0NNN` calls to machine code do
+    #[arg(long, value_parser = PossibleValuesParser::new(MachineCode::NAMES))]
+    pub machine_code: Option<String>,
+    /// Reports every diagnostic (stack overflows, memory accesses...) the same way,
+    /// instead of as the configuration says
+    #[arg(long, value_parser = PossibleValuesParser::new(Level::NAMES))]
+    pub diagnostics: Option<String>,
+    /// Anti-flicker display mode
+    #[arg(long, value_parser = PossibleValuesParser::new(DisplayMode::NAMES))]
+    pub display: Option<String>,
+    /// Window size as a multiple of the 64x32 display
+    #[arg(long)]
+    pub scale: Option<u32>,
+    /// How the display fits in the window
+    #[arg(long, value_parser = PossibleValuesParser::new(Scaling::NAMES))]
+    pub scaling: Option<String>,
+    /// Palette name (classic, amber, green, lcd, octo), or colours:
+    /// foreground and background, then the XO-CHIP second plane and blend,
+    /// e.g. `FFFFFF,000000`
+    #[arg(long, value_parser = parse_palette)]
+    pub palette: Option<ColorConfig>,
+    /// Seed for the random number generator, for reproducible runs
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Address the ROM is loaded at and executed from, 0x200 unless the platform says otherwise
+    #[arg(long, value_parser = parse_address)]
+    pub start: Option<u16>,
+    /// Font of `FX29`, as the interpreter of one of these machines had it
+    #[arg(long, value_parser = PossibleValuesParser::new(Font::SMALL))]
+    pub font: Option<String>,
+    /// Font of the SUPER-CHIP `FX30`
+    #[arg(long, value_parser = PossibleValuesParser::new(Font::BIG))]
+    pub big_font: Option<String>,
+    /// Font file replacing the built-in ones: 80 bytes of small glyphs, optionally followed by big glyphs of 10 bytes
+    #[arg(long)]
+    pub font_file: Option<PathBuf>,
+    /// Address the font is kept at
+    #[arg(long, value_parser = parse_address)]
+    pub font_address: Option<u16>,
+    /// Doesn't beep
+    #[arg(long, overrides_with = "no_mute")]
+    pub mute: bool,
+    /// Beeps, even if the configuration mutes
+    #[arg(long, overrides_with = "mute")]
+    pub no_mute: bool,
+    #[arg(long, overrides_with = "windowed")]
+    pub fullscreen: bool,
+    /// Opens a window, even if the configuration says fullscreen
+    #[arg(long, overrides_with = "fullscreen")]
+    pub windowed: bool,
+    /// Post-processing preset: none, phosphor, crt, or a preset file
+    #[arg(long)]
+    pub shader: Option<String>,
+    /// Video backend
+    #[arg(long, value_enum, default_value_t = Video::Window)]
+    pub video: Video,
+    /// Directory the PNG backend writes frames to
+    #[arg(long, default_value = "frames")]
+    pub output: PathBuf,
+    /// Runs without a window, same as `--video none`
+    #[arg(long)]
+    pub headless: bool,
+    /// Stops after this many frames
+    #[arg(long)]
+    pub frames: Option<u64>,
+    /// Records from the start to a `.gif` or `.y4m` file, F12 starts and stops recordings too
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Size of recordings, as a multiple of the display
+    #[arg(long, default_value_t = 1)]
+    pub record_scale: u32,
+    /// Writes the sampled sound (MegaChip) to a raw file, unsigned 8-bit mono at 44100Hz
+    #[arg(long)]
+    pub audio: Option<PathBuf>,
+    /// Saves the display to a PNG file once the run ends, F2 takes screenshots while playing
+    #[arg(long)]
+    pub screenshot: Option<PathBuf>,
+    /// Size of screenshots, as a multiple of the display
+    #[arg(long, default_value_t = 1)]
+    pub screenshot_scale: u32,
+    /// Embeds the ROM hash, the program counter and the frame number in screenshots
+    #[arg(long)]
+    pub screenshot_info: bool,
+    /// Remembers the speed, quirks and palette given here for this ROM
+    #[arg(long)]
+    pub save: bool,
+}
+
+pub fn parse_address(value: &str) -> Result<u16, String> {
+    let address = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|error| error.to_string())?;
+
+    if address > 0xFFF {
+        return Err(format!("0x{:X} is outside of memory", address));
+    }
+    Ok(address)
+}
+
+fn parse_palette(value: &str) -> Result<ColorConfig, String> {
+    if Palette::NAMES.contains(&value) {
+        return Ok(ColorConfig {
+            palette: Some(value.to_string()),
+            ..ColorConfig::from_colors(&[])
+        });
+    }
+
+    let mut colors: Vec<String> = value.split(',').map(str::to_string).collect();
+    if colors.len() < 2 || colors.len() > 4 {
+        return Err(format!(
+            "expected one of {} or `foreground,background[,foreground2,blend]`",
+            Palette::NAMES.join(", ")
+        ));
+    }
+    for color in &colors {
+        graphics::parse_color(color)?;
+    }
+    colors.swap(0, 1);
+    Ok(ColorConfig::from_colors(&colors))
+}
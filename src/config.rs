@@ -0,0 +1,605 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{self, RunOptions};
+use crate::cpu::{self, Diagnostics, DisplayMode, Level, MachineCode, Platform, Quirks, Routine, Timing};
+use crate::font::Font;
+use crate::gamepad::{self, Control, Mapping};
+use crate::graphics::{self, Scaling};
+use crate::palette::Palette;
+
+/// Settings stored in `config.toml` inside the user config directory
+/// (`~/.config/chip8` on Linux). Command line flags take precedence
+/// over the file, and a `[roms.<sha1>]` section over the global values.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub emulation: EmulationConfig,
+    pub diagnostics: DiagnosticsConfig,
+    pub colors: ColorConfig,
+    /// Per-ROM settings, keyed by the SHA-1 of the ROM.
+    pub roms: BTreeMap<String, RomConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub multisampling: u16,
+    /// Post-processing preset, see `postfx::load`.
+    pub shader: String,
+    /// `stretch`, `fit` or `integer`, see `Scaling`.
+    pub scaling: String,
+    /// Space around the display, in pixels.
+    pub border: u32,
+    /// Where the window was last time, remembered when it's closed like its size.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<(i32, i32)>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+            fullscreen: false,
+            vsync: true,
+            multisampling: 4,
+            shader: "none".to_string(),
+            scaling: "fit".to_string(),
+            border: 0,
+            position: None,
+        }
+    }
+}
+
+impl WindowConfig {
+    /// Keeps the size and the position of the window, returning whether they changed.
+    pub fn remember(&mut self, (width, height, position): (u32, u32, (i32, i32))) -> bool {
+        let changed = (self.width, self.height, self.position) != (width, height, Some(position));
+        self.width = width;
+        self.height = height;
+        self.position = Some(position);
+        changed
+    }
+}
+
+/// How every diagnostic is reported: `ignore`, `warn` or `break`, see `Diagnostics`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct DiagnosticsConfig {
+    pub stack_overflow: String,
+    pub stack_underflow: String,
+    pub memory_bounds: String,
+    pub uninitialized_read: String,
+    pub protected_write: String,
+    pub self_modifying: String,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            stack_overflow: "warn".to_string(),
+            stack_underflow: "warn".to_string(),
+            memory_bounds: "warn".to_string(),
+            uninitialized_read: "ignore".to_string(),
+            protected_write: "ignore".to_string(),
+            self_modifying: "ignore".to_string(),
+        }
+    }
+}
+
+impl DiagnosticsConfig {
+    pub fn diagnostics(&self) -> Result<Diagnostics, String> {
+        let level = |name: &String| Level::from_name(name).ok_or_else(|| format!("unknown diagnostic level `{}`", name));
+        Ok(Diagnostics {
+            stack_overflow: level(&self.stack_overflow)?,
+            stack_underflow: level(&self.stack_underflow)?,
+            memory_bounds: level(&self.memory_bounds)?,
+            uninitialized_read: level(&self.uninitialized_read)?,
+            protected_write: level(&self.protected_write)?,
+            self_modifying: level(&self.self_modifying)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct EmulationConfig {
+    /// Instructions executed per frame.
+    pub speed: u32,
+    /// The computer games are written for, see `Platform`.
+    pub platform: String,
+    /// Quirk preset name, see `Quirks::preset`.
+    pub quirks: String,
+    /// `fixed` or `vip`, see `Timing`.
+    pub timing: String,
+    /// What `0NNN` does, see `MachineCode`.
+    pub machine_code: String,
+    /// Anti-flicker mode, see `DisplayMode`.
+    pub display: String,
+    /// The small and big fonts, see `Font`, and the address they are kept at.
+    pub font: String,
+    pub big_font: String,
+    pub font_address: String,
+    /// A font file used instead of the built-in fonts, see `Font::load`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_file: Option<PathBuf>,
+    pub mute: bool,
+    /// A copy of the community ROM database to use instead of the bundled one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<PathBuf>,
+    /// The CHIP-8 interpreter and the monitor ROM of the COSMAC VIP, see `Vip`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<PathBuf>,
+}
+
+impl Default for EmulationConfig {
+    fn default() -> Self {
+        Self {
+            speed: 10,
+            platform: "chip8".to_string(),
+            quirks: "default".to_string(),
+            timing: "fixed".to_string(),
+            machine_code: "error".to_string(),
+            display: "immediate".to_string(),
+            font: "default".to_string(),
+            big_font: "schip".to_string(),
+            font_address: format!("0x{:03X}", cpu::FONT_ADDRESS),
+            font_file: None,
+            mute: false,
+            database: None,
+            interpreter: None,
+            monitor: None,
+        }
+    }
+}
+
+/// A named palette (`classic`, `amber`, `green`, `lcd` or `octo`),
+/// with any of its colours replaced by `RRGGBB` hex strings.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ColorConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub foreground: Option<String>,
+    /// Pixels lit on the second XO-CHIP plane only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub foreground2: Option<String>,
+    /// Pixels lit on both XO-CHIP planes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blend: Option<String>,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        Self {
+            palette: Some("classic".to_string()),
+            background: None,
+            foreground: None,
+            foreground2: None,
+            blend: None,
+        }
+    }
+}
+
+impl ColorConfig {
+    /// Colours given as a list, background first, like the ROM database does.
+    pub fn from_colors(colors: &[String]) -> Self {
+        let color = |index: usize| colors.get(index).map(|color| color.trim_start_matches('#').to_string());
+        Self {
+            palette: None,
+            background: color(0),
+            foreground: color(1),
+            foreground2: color(2),
+            blend: color(3),
+        }
+    }
+
+    pub fn palette(&self) -> Result<Palette, String> {
+        let name = self.palette.as_deref().unwrap_or("classic");
+        let mut palette = Palette::named(name).ok_or_else(|| format!("unknown palette `{}`", name))?;
+
+        let colors = [&self.background, &self.foreground, &self.foreground2, &self.blend];
+        for (index, color) in colors.iter().enumerate() {
+            if let Some(color) = color {
+                palette.set(index, graphics::parse_color(color)?);
+            }
+        }
+        Ok(palette)
+    }
+}
+
+/// Settings a single game remembers, anything unset uses the global value.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct RomConfig {
+    /// Only there to tell the sections apart when editing the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colors: Option<ColorConfig>,
+    /// The small font the game was written with, see `Font`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    /// Keyboard keys (`W`, `Up`, `Space`...) to keypad keys (`0`-`F`).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keyboard: BTreeMap<String, String>,
+    /// Gamepad controls (`South`, `DPadUp`...) to keypad keys (`0`-`F` or `none`).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub gamepad: BTreeMap<String, String>,
+    /// Addresses of machine code routines (`0x2A0`...) to what runs instead of them
+    /// (`nop` or `clear`), with the `native` machine code policy.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub routines: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8").join("config.toml"))
+    }
+
+    /// Reads the configuration, the defaults until the file is saved once.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = fs::read_to_string(path).map_err(|error| format!("{:?}: {}", path, error))?;
+        toml::from_str(&content).map_err(|error| format!("{:?}: {}", path, error))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = toml::to_string_pretty(self).map_err(|error| error.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|error| format!("{:?}: {}", dir, error))?;
+        }
+        fs::write(path, content).map_err(|error| format!("{:?}: {}", path, error))
+    }
+}
+
+/// Settings suggested by the ROM itself or by the ROM database.
+#[derive(Default)]
+pub struct Hints {
+    pub speed: Option<u32>,
+    pub quirks: Option<Quirks>,
+    pub colors: Option<ColorConfig>,
+    /// What keypad keys do, e.g. `up = 5` or `a = 6`.
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl Hints {
+    /// Fills what these hints don't say with the ones from `fallback`.
+    pub fn or(self, fallback: Hints) -> Hints {
+        let mut keys = fallback.keys;
+        keys.extend(self.keys);
+
+        Hints {
+            speed: self.speed.or(fallback.speed),
+            quirks: self.quirks.or(fallback.quirks),
+            colors: self.colors.or(fallback.colors),
+            keys,
+        }
+    }
+}
+
+/// Everything a run needs, once the file, the ROM section and the flags are merged.
+pub struct Settings {
+    pub platform: Platform,
+    /// Where the program is loaded and executed from.
+    pub start: u16,
+    pub speed: u32,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub machine_code: MachineCode,
+    pub diagnostics: Diagnostics,
+    /// Host versions of the machine code routines of the ROM, by address.
+    pub routines: BTreeMap<u16, Routine>,
+    pub display: DisplayMode,
+    pub font: Font,
+    pub font_address: u16,
+    pub palette: Palette,
+    pub mute: bool,
+    pub window: WindowConfig,
+    pub scaling: Scaling,
+    /// Keyboard key names, as `VirtualKeyCode` prints them, to keypad keys.
+    pub keyboard: BTreeMap<String, u8>,
+    pub gamepad: Mapping,
+}
+
+/// Key names of the default layout, as `VirtualKeyCode` prints them.
+const KEYBOARD_LAYOUT: [(&str, u8); 16] = [
+    ("Key1", 0x1), ("Key2", 0x2), ("Key3", 0x3), ("Key4", 0xC),
+    ("Q", 0x4), ("W", 0x5), ("E", 0x6), ("R", 0xD),
+    ("A", 0x7), ("S", 0x8), ("D", 0x9), ("F", 0xE),
+    ("Z", 0xA), ("X", 0x0), ("C", 0xB), ("V", 0xF),
+];
+
+/// The second keypad of CHIP-8X on the numeric keypad, laid out like the first.
+const SECOND_KEYBOARD_LAYOUT: [(&str, u8); 16] = [
+    ("Numpad7", 0x1), ("Numpad8", 0x2), ("Numpad9", 0x3), ("Divide", 0xC),
+    ("Numpad4", 0x4), ("Numpad5", 0x5), ("Numpad6", 0x6), ("Multiply", 0xD),
+    ("Numpad1", 0x7), ("Numpad2", 0x8), ("Numpad3", 0x9), ("Subtract", 0xE),
+    ("Numpad0", 0xA), ("Decimal", 0x0), ("NumpadEnter", 0xB), ("Add", 0xF),
+];
+
+impl Settings {
+    ///```
+    ///Keypad                   Keyboard
+    ///+-+-+-+-+                +-+-+-+-+
+    ///|1|2|3|C|                |1|2|3|4|
+    ///+-+-+-+-+                +-+-+-+-+
+    ///|4|5|6|D|                |Q|W|E|R|
+    ///+-+-+-+-+       =>       +-+-+-+-+
+    ///|7|8|9|E|                |A|S|D|F|
+    ///+-+-+-+-+                +-+-+-+-+
+    ///|A|0|B|F|                |Z|X|C|V|
+    ///+-+-+-+-+                +-+-+-+-+
+    ///```
+    /// Keys remapped in the ROM configuration take precedence over this layout.
+    pub fn keypad_key(&self, name: &str) -> Option<u8> {
+        if let Some(key) = self.keyboard.get(name) {
+            return Some(*key);
+        }
+        layout_key(&KEYBOARD_LAYOUT, name)
+    }
+
+    /// The key of the second keypad of CHIP-8X, on the numeric keypad.
+    pub fn second_keypad_key(&self, name: &str) -> Option<u8> {
+        layout_key(&SECOND_KEYBOARD_LAYOUT, name)
+    }
+}
+
+fn layout_key(layout: &[(&str, u8)], name: &str) -> Option<u8> {
+    layout.iter().find(|(key_name, _)| *key_name == name).map(|(_, key)| *key)
+}
+
+impl Config {
+    /// Merges the global settings, the hints from the ROM and the database,
+    /// the section of the ROM with the given hash and the command line flags,
+    /// in increasing order of precedence. `program_size` is checked against the font.
+    pub fn resolve(&self, hash: &str, hints: &Hints, options: &RunOptions, program_size: usize) -> Result<Settings, String> {
+        let rom = self.roms.get(hash);
+
+        let speed = options
+            .speed
+            .or_else(|| rom.and_then(|rom| rom.speed))
+            .or(hints.speed)
+            .unwrap_or(self.emulation.speed);
+
+        let platform = options
+            .platform
+            .as_ref()
+            .or_else(|| rom.and_then(|rom| rom.platform.as_ref()))
+            .unwrap_or(&self.emulation.platform);
+        let platform = Platform::from_name(platform).ok_or_else(|| format!("unknown platform `{}`", platform))?;
+        let start = options.start.unwrap_or_else(|| platform.start());
+        if start as usize >= platform.memory_size() {
+            return Err(format!("the start 0x{:03X} is past the 0x{:X} bytes of memory", start, platform.memory_size()));
+        }
+
+        let preset = options
+            .quirks
+            .as_ref()
+            .or_else(|| rom.and_then(|rom| rom.quirks.as_ref()));
+        let quirks = match (preset, platform.quirks().or(hints.quirks)) {
+            (None, Some(quirks)) => quirks,
+            (preset, _) => {
+                let preset = preset.unwrap_or(&self.emulation.quirks);
+                Quirks::preset(preset).ok_or_else(|| format!("unknown quirks `{}`", preset))?
+            }
+        };
+
+        let timing = options.timing.as_ref().unwrap_or(&self.emulation.timing);
+        let timing = Timing::from_name(timing).ok_or_else(|| format!("unknown timing `{}`", timing))?;
+
+        let machine_code = options.machine_code.as_ref().unwrap_or(&self.emulation.machine_code);
+        let machine_code = MachineCode::from_name(machine_code)
+            .ok_or_else(|| format!("unknown machine code policy `{}`", machine_code))?;
+        let mut routines = BTreeMap::new();
+        for (address, name) in rom.iter().flat_map(|rom| &rom.routines) {
+            let routine = cpu::routine(name).ok_or_else(|| format!("unknown routine `{}`", name))?;
+            routines.insert(cli::parse_address(address)?, routine);
+        }
+
+        let diagnostics = match &options.diagnostics {
+            Some(level) => Diagnostics::all(Level::from_name(level).ok_or_else(|| format!("unknown diagnostic level `{}`", level))?),
+            None => self.diagnostics.diagnostics()?,
+        };
+
+        let display = options.display.as_ref().unwrap_or(&self.emulation.display);
+        let display = DisplayMode::from_name(display).ok_or_else(|| format!("unknown display mode `{}`", display))?;
+
+        let small = options
+            .font
+            .as_deref()
+            .or_else(|| rom.and_then(|rom| rom.font.as_deref()))
+            .unwrap_or(match platform {
+                Platform::Chip8 => &self.emulation.font,
+                _ => platform.font(),
+            });
+        let big = options.big_font.as_ref().unwrap_or(&self.emulation.big_font);
+        let mut font = Font::named(small, big).ok_or_else(|| format!("unknown font `{}` or `{}`", small, big))?;
+        if let Some(path) = options.font_file.as_ref().or(self.emulation.font_file.as_ref()) {
+            font = font.load(path)?;
+        }
+        let font_address = match options.font_address {
+            Some(address) => address,
+            None => cli::parse_address(&self.emulation.font_address)?,
+        };
+        if font_address as usize + font.size() > platform.memory_size() {
+            return Err(format!("the font doesn't fit in memory at 0x{:03X}", font_address));
+        }
+        let (font_end, program_end) = (font_address as usize + font.size(), start as usize + program_size);
+        if (font_address as usize) < program_end && (start as usize) < font_end {
+            return Err(format!(
+                "the font at 0x{:03X}-0x{:03X} overlaps the program at 0x{:03X}-0x{:03X}",
+                font_address,
+                font_end - 1,
+                start,
+                program_end - 1
+            ));
+        }
+
+        let colors = options
+            .palette
+            .as_ref()
+            .or_else(|| rom.and_then(|rom| rom.colors.as_ref()))
+            .or(hints.colors.as_ref())
+            .unwrap_or(&self.colors);
+
+        let mut window = self.window.clone();
+        if let Some(scale) = options.scale {
+            let (width, height) = platform.display();
+            window.width = width as u32 * scale + 2 * window.border;
+            window.height = height as u32 * scale + 2 * window.border;
+        }
+        let scaling = options.scaling.as_ref().unwrap_or(&window.scaling);
+        let scaling = Scaling::from_name(scaling).ok_or_else(|| format!("unknown scaling `{}`", scaling))?;
+        if options.fullscreen || options.windowed {
+            window.fullscreen = options.fullscreen;
+        }
+        if let Some(shader) = &options.shader {
+            window.shader = shader.clone();
+        }
+
+        let mut keyboard = BTreeMap::new();
+        let mut gamepad = Mapping::default();
+        bind_key_hints(&mut gamepad, &hints.keys);
+        gamepad.apply_rom_profile(&options.rom);
+        if let Some(rom) = rom {
+            for (name, key) in &rom.keyboard {
+                keyboard.insert(name.clone(), gamepad::parse_key(key)?);
+            }
+            for (name, key) in &rom.gamepad {
+                gamepad.bind(name, key)?;
+            }
+        }
+
+        Ok(Settings {
+            platform,
+            start,
+            speed,
+            quirks,
+            timing,
+            machine_code,
+            diagnostics,
+            routines,
+            display,
+            font,
+            font_address,
+            palette: colors.palette()?,
+            mute: if options.mute || options.no_mute { options.mute } else { self.emulation.mute },
+            window,
+            scaling,
+            keyboard,
+            gamepad,
+        })
+    }
+
+    /// Stores the speed, quirks and palette given on the command line in the ROM section.
+    pub fn remember(&mut self, hash: &str, options: &RunOptions) {
+        let rom = self.roms.entry(hash.to_string()).or_default();
+        rom.name = options
+            .rom
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        if options.speed.is_some() {
+            rom.speed = options.speed;
+        }
+        if options.quirks.is_some() {
+            rom.quirks = options.quirks.clone();
+        }
+        if options.palette.is_some() {
+            rom.colors = options.palette.clone();
+        }
+    }
+}
+
+/// Puts the keys the database describes for a game on the matching controls,
+/// so the D-pad moves and the face buttons act without any configuration.
+fn bind_key_hints(mapping: &mut Mapping, keys: &BTreeMap<String, u8>) {
+    for (hint, key) in keys {
+        let controls: &[Control] = match hint.as_str() {
+            "up" => &[Control::DPadUp, Control::LeftStickUp],
+            "down" => &[Control::DPadDown, Control::LeftStickDown],
+            "left" => &[Control::DPadLeft, Control::LeftStickLeft],
+            "right" => &[Control::DPadRight, Control::LeftStickRight],
+            "a" => &[Control::South],
+            "b" => &[Control::East],
+            _ => &[],
+        };
+
+        for control in controls {
+            mapping.set(*control, *key & 0xF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Command};
+    use clap::Parser;
+
+    fn settings(config: &Config, flags: &[&str]) -> Settings {
+        let arguments = ["chip8", "run", "game.ch8"].iter().chain(flags);
+        let options = match Cli::parse_from(arguments).command {
+            Command::Run(options) => options,
+            _ => unreachable!(),
+        };
+        config.resolve("", &Hints::default(), &options, 0).unwrap()
+    }
+
+    #[test]
+    fn starts_past_the_memory_of_the_platform_are_refused() {
+        let arguments = ["chip8", "run", "game.ch8", "--platform", "dream6800", "--start", "0x900"];
+        let options = match Cli::parse_from(arguments.iter()).command {
+            Command::Run(options) => options,
+            _ => unreachable!(),
+        };
+        let error = Config::default().resolve("", &Hints::default(), &options, 0).err().unwrap();
+        assert_eq!(error, "the start 0x900 is past the 0x800 bytes of memory");
+        assert_eq!(settings(&Config::default(), &["--platform", "dream6800", "--start", "0x7FE"]).start, 0x7FE);
+    }
+
+    #[test]
+    fn flags_override_the_file_both_ways() {
+        let mut config = Config::default();
+        assert!(!settings(&config, &[]).mute);
+        assert!(settings(&config, &["--mute"]).mute);
+
+        config.emulation.mute = true;
+        config.window.fullscreen = true;
+        let file = settings(&config, &[]);
+        assert!(file.mute && file.window.fullscreen);
+        let flags = settings(&config, &["--no-mute", "--windowed"]);
+        assert!(!flags.mute && !flags.window.fullscreen);
+        assert!(settings(&config, &["--no-mute", "--mute"]).mute, "the last flag wins");
+    }
+
+    #[test]
+    fn loading_a_missing_file_doesnt_create_it() {
+        let path = std::env::temp_dir().join(format!("chip8-missing-{}.toml", std::process::id()));
+        assert_eq!(Config::load(&path).unwrap().emulation.speed, Config::default().emulation.speed);
+        assert!(!path.exists());
+    }
+}
@@ -1,45 +1,514 @@
-use rand::Rng;
-
-const SCREEN_WIDTH: u16 = 64;
-const SCREEN_HEIGHT: u16 = 32;
-const SCREEN_SIZE: u16 = SCREEN_WIDTH * SCREEN_HEIGHT;
-
-const MEMORY_SIZE: u16 = 4096;
-
-const FONT_NUMBER: usize = 80;
-/// Each font number is 5 bytes long.
-const FONTSET: [u8; FONT_NUMBER] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::font::{Font, BIG_GLYPH, SMALL_GLYPH};
+use crate::machine::{Halt, Machine, AUDIO_RATE};
+use crate::graphics::Color;
+use crate::video::{ColorMap, Frame};
+
+/// Where the font is kept unless configured otherwise.
+pub const FONT_ADDRESS: u16 = 0x050;
+
+/// Behaviours that differ between CHIP-8 interpreters.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift Vy into Vx, instead of shifting Vx in place.
+    pub shift_vy: bool,
+    /// `FX55`/`FX65` leave I pointing past the last register they touched.
+    pub load_store_increment: bool,
+    /// `BNNN` jumps to `NNN + VX` (read as `BXNN`), instead of `NNN + V0`.
+    pub jump_vx: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to zero.
+    pub vf_reset: bool,
+    /// `DXYN` cuts sprites off at the edges of the screen, instead of wrapping them around.
+    pub clip_sprites: bool,
+    /// `DXYN` sets VF to the number of rows that collided or were clipped at the bottom,
+    /// like SUPER-CHIP, instead of 1 on any collision.
+    pub vf_row_count: bool,
+    /// `FX0A` waits for the key to be released, like the VIP, instead of returning as soon as it's pressed.
+    pub key_release: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_vy: false,
+            load_store_increment: true,
+            jump_vx: false,
+            vf_reset: false,
+            clip_sprites: true,
+            vf_row_count: false,
+            key_release: false,
+        }
+    }
+}
+
+impl Quirks {
+    pub const PRESETS: [&'static str; 4] = ["default", "vip", "schip", "xochip"];
+
+    /// `vip` is the original COSMAC VIP interpreter, `schip` is SUPER-CHIP 1.1,
+    /// `xochip` is what Octo does by default and `default` is what this emulator has always done.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "vip" => Some(Self {
+                shift_vy: true,
+                load_store_increment: true,
+                jump_vx: false,
+                vf_reset: true,
+                clip_sprites: true,
+                vf_row_count: false,
+                key_release: true,
+            }),
+            "schip" => Some(Self {
+                shift_vy: false,
+                load_store_increment: false,
+                jump_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                vf_row_count: true,
+                key_release: false,
+            }),
+            "xochip" => Some(Self {
+                shift_vy: true,
+                load_store_increment: true,
+                jump_vx: false,
+                vf_reset: false,
+                clip_sprites: false,
+                vf_row_count: false,
+                key_release: true,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// How the display is turned into what the front-ends show. Sprites are drawn
+/// with XOR, so games erase and draw objects again every frame, and
+/// showing the display as it is makes them flicker.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DisplayMode {
+    /// Shows every change as soon as it is drawn.
+    Immediate,
+    /// Shows the display as it is at the 60Hz vertical blank, like the VIP did.
+    Vblank,
+    /// Shows a pixel if it was lit at either of the last two vertical blanks.
+    Blend,
+    /// Lit pixels fade out over a few frames instead of going dark at once.
+    Decay,
+}
+
+impl DisplayMode {
+    pub const NAMES: [&'static str; 4] = ["immediate", "vblank", "blend", "decay"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "immediate" => Some(Self::Immediate),
+            "vblank" => Some(Self::Vblank),
+            "blend" => Some(Self::Blend),
+            "decay" => Some(Self::Decay),
+            _ => None,
+        }
+    }
+}
+
+/// How many instructions run in a 60Hz frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Timing {
+    /// The speed, as instructions per frame.
+    Fixed,
+    /// As many as the COSMAC VIP interpreter fit in a frame: every instruction takes
+    /// the machine cycles it took there, and `DXYN` waits for the vertical blank.
+    Vip,
+}
+
+impl Timing {
+    pub const NAMES: [&'static str; 2] = ["fixed", "vip"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fixed" => Some(Self::Fixed),
+            "vip" => Some(Self::Vip),
+            _ => None,
+        }
+    }
+}
+
+/// Machine cycles of the VIP in a frame, 8 clock cycles each at 1.7609 MHz.
+const VIP_FRAME_CYCLES: i32 = 1_760_900 / 8 / 60;
+/// Taken from every frame by the CDP1861 fetching the display, 8 bytes on each of
+/// its 128 lines, and by the interrupt routine that counts the timers down.
+const VIP_DISPLAY_CYCLES: i32 = 128 * 8 + 46;
+/// Fetching an instruction and jumping to its code, whatever it is.
+const VIP_FETCH_CYCLES: i32 = 40;
+
+/// How a diagnostic is reported.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Level {
+    Ignore,
+    /// Prints a warning, once for every instruction doing it.
+    Warn,
+    /// Pauses the emulation once the instruction is done.
+    Break,
+}
+
+impl Level {
+    pub const NAMES: [&'static str; 3] = ["ignore", "warn", "break"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ignore" => Some(Self::Ignore),
+            "warn" => Some(Self::Warn),
+            "break" => Some(Self::Break),
+            _ => None,
+        }
+    }
+}
+
+/// Things programs do that are most likely bugs, and how each is reported.
+/// The emulation goes on as if nothing happened: the stack and memory accesses wrap around.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    /// `2NNN` with the 16 levels of the stack in use.
+    pub stack_overflow: Level,
+    /// `00EE` with an empty stack.
+    pub stack_underflow: Level,
+    /// Memory accessed through I past its end, `0xFFF` on most platforms.
+    pub memory_bounds: Level,
+    /// Memory read or executed before anything was loaded or written there.
+    pub uninitialized_read: Level,
+    /// Memory written below the start of the program, where the interpreter and the font are.
+    pub protected_write: Level,
+    /// Memory written after it was executed.
+    pub self_modifying: Level,
+}
+
+impl Diagnostics {
+    /// Every diagnostic reported the same way.
+    pub fn all(level: Level) -> Self {
+        Self {
+            stack_overflow: level,
+            stack_underflow: level,
+            memory_bounds: level,
+            uninitialized_read: level,
+            protected_write: level,
+            self_modifying: level,
+        }
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self {
+            stack_overflow: Level::Warn,
+            stack_underflow: Level::Warn,
+            memory_bounds: Level::Warn,
+            ..Self::all(Level::Ignore)
+        }
+    }
+}
+
+/// What `0NNN` does: on the VIP it ran the 1802 machine code at `NNN`,
+/// which can't be interpreted here.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MachineCode {
+    /// Skips the call.
+    Ignore,
+    /// Pauses the emulation, to look at what the game was doing.
+    Break,
+    /// Stops the emulation with `Error::MachineCode`.
+    Error,
+    /// Runs the `Routine` registered for `NNN`, stopping like `Error` without one.
+    /// None are registered by default: the machine code is the game's own,
+    /// so every ROM's configuration says what its routines do.
+    Native,
+}
+
+impl MachineCode {
+    pub const NAMES: [&'static str; 4] = ["ignore", "break", "error", "native"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ignore" => Some(Self::Ignore),
+            "break" => Some(Self::Break),
+            "error" => Some(Self::Error),
+            "native" => Some(Self::Native),
+            _ => None,
+        }
+    }
+}
+
+/// A computer CHIP-8 ran on, and what its interpreter did differently.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Platform {
+    /// The COSMAC VIP memory map, with 64x32 pixels, like most games expect.
+    Chip8,
+    /// **ETI-660**, another 1802 kit: programs start at `0x600`, after its
+    /// interpreter and the bigger display buffer of its 64x48 pixels.
+    Eti660,
+    /// **DREAM 6800** and its CHIPOS interpreter for the 6800, in 2K of RAM.
+    Dream6800,
+    /// **CHIP-8X**, the VIP with the VP-590 colour board and a second keypad.
+    /// Its bigger interpreter starts programs at `0x300`.
+    Chip8x,
+    /// **MegaChip 8**, SUPER-CHIP with a 256x192 display in 256 colours,
+    /// 24-bit addresses and sampled sound, once `0011` turns its mode on.
+    MegaChip,
+}
+
+impl Platform {
+    pub const NAMES: [&'static str; 5] = ["chip8", "eti660", "dream6800", "chip8x", "megachip"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Self::Chip8),
+            "eti660" => Some(Self::Eti660),
+            "dream6800" => Some(Self::Dream6800),
+            "chip8x" => Some(Self::Chip8x),
+            "megachip" => Some(Self::MegaChip),
+            _ => None,
+        }
+    }
+
+    /// Where programs are loaded and executed from.
+    pub fn start(self) -> u16 {
+        match self {
+            Self::Eti660 => 0x600,
+            Self::Chip8x => 0x300,
+            Self::Chip8 | Self::Dream6800 | Self::MegaChip => 0x200,
+        }
+    }
+
+    pub fn memory_size(self) -> usize {
+        match self {
+            Self::Chip8 | Self::Eti660 | Self::Chip8x => 0x1000,
+            Self::Dream6800 => 0x800,
+            Self::MegaChip => 0x100_0000,
+        }
+    }
+
+    /// Width and height of the display, in pixels. MegaChip programs switch to 256x192 themselves.
+    pub fn display(self) -> (usize, usize) {
+        match self {
+            Self::Eti660 => (64, 48),
+            Self::Chip8 | Self::Dream6800 | Self::Chip8x | Self::MegaChip => (64, 32),
+        }
+    }
+
+    /// The small font of its interpreter, see `Font`.
+    pub fn font(self) -> &'static str {
+        match self {
+            Self::Chip8 | Self::MegaChip => "default",
+            Self::Eti660 => "eti660",
+            Self::Dream6800 => "dream6800",
+            Self::Chip8x => "vip",
+        }
+    }
+
+    /// The other interpreters were written after the one of the VIP and behave like it,
+    /// except CHIPOS which returns from `FX0A` as soon as a key is pressed.
+    /// MegaChip extends SUPER-CHIP.
+    pub fn quirks(self) -> Option<Quirks> {
+        match self {
+            Self::Chip8 => None,
+            Self::Eti660 | Self::Chip8x => Quirks::preset("vip"),
+            Self::Dream6800 => Quirks::preset("vip").map(|quirks| Quirks { key_release: false, ..quirks }),
+            Self::MegaChip => Quirks::preset("schip"),
+        }
+    }
+}
+
+/// Foreground colours of the VP-590, as `BXYN` sets them in `V(X+1)`.
+const CHIP8X_COLORS: [Color; 8] = [
+    (0x00, 0x00, 0x00), // black
+    (0xFF, 0x00, 0x00), // red
+    (0x00, 0x00, 0xFF), // blue
+    (0xFF, 0x00, 0xFF), // violet
+    (0x00, 0xFF, 0x00), // green
+    (0xFF, 0xFF, 0x00), // yellow
+    (0x00, 0xFF, 0xFF), // aqua
+    (0xFF, 0xFF, 0xFF), // white
+];
+/// Background colours `02A0` cycles through.
+const CHIP8X_BACKGROUNDS: [Color; 4] = [
+    (0x00, 0x00, 0x80), // blue
+    (0x00, 0x00, 0x00), // black
+    (0x00, 0x80, 0x00), // green
+    (0x80, 0x00, 0x00), // red
 ];
+/// The colour board splits the display in 8 columns of 8 pixels, on every row.
+const ZONE_COLUMNS: usize = 8;
+const ZONE_ROWS: usize = 32;
+
+/// The display of MegaChip mode.
+const MEGACHIP_WIDTH: usize = 256;
+const MEGACHIP_HEIGHT: usize = 192;
+
+/// What MegaChip mode adds to the machine, from `0011` until `0010`.
+struct MegaChip {
+    palette: [Color; 256], // loaded by `02NN` from index 1, 0 is transparent
+    sprite_width: usize,
+    sprite_height: usize,
+    alpha: u8,             // of the whole screen
+    blend: Blend,
+    collision_color: u8,   // `DXYN` sets VF when drawing over it
+    buffer: Vec<Color>,    // what `DXYN` draws into, shown by the next `00E0`
+    indices: Vec<u8>,      // palette index of every pixel of `buffer`
+    screen: Vec<Color>,    // what the front-ends show
+    sample: Option<Sample>,
+    audio: Vec<u8>,        // what `sample` played during the last frame, see `AUDIO_RATE`
+}
+
+impl MegaChip {
+    fn new() -> Self {
+        let size = MEGACHIP_WIDTH * MEGACHIP_HEIGHT;
+        Self {
+            palette: [(0, 0, 0); 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend: Blend::Normal,
+            collision_color: 0,
+            buffer: vec![(0, 0, 0); size],
+            indices: vec![0; size],
+            screen: vec![(0, 0, 0); size],
+            sample: None,
+            audio: Vec::new(),
+        }
+    }
+}
+
+/// How MegaChip sprites are mixed with what they are drawn over, set by `080N`.
+#[derive(Copy, Clone)]
+enum Blend {
+    Normal,
+    /// The sprite shows through by this much, out of 255.
+    Opacity(u8),
+    Add,
+    Multiply,
+}
+
+impl Blend {
+    fn mix(self, under: Color, over: Color) -> Color {
+        let channel = |under: u8, over: u8| match self {
+            Blend::Normal => over,
+            Blend::Opacity(amount) => (under as i32 + (over as i32 - under as i32) * amount as i32 / 255) as u8,
+            Blend::Add => under.saturating_add(over),
+            Blend::Multiply => (under as u32 * over as u32 / 255) as u8,
+        };
+        (channel(under.0, over.0), channel(under.1, over.1), channel(under.2, over.2))
+    }
+}
+
+/// A MegaChip sound, 8-bit unsigned samples played from memory by `060N`.
+struct Sample {
+    start: u32,
+    length: u32,
+    rate: u32,
+    looping: bool,
+    /// Samples played, times 60 so that every frame adds the rate.
+    played: u64,
+}
+
+/// What a machine code routine does, implemented on the host, see `MachineCode::Native`.
+pub type Routine = fn(&mut Chip8);
+
+/// The routines games can be set up to call, by name. These are generic stand-ins,
+/// not translations of any particular 1802 code.
+pub const ROUTINES: [(&str, Routine); 2] = [("nop", nop), ("clear", clear)];
+
+fn nop(_: &mut Chip8) {}
+
+/// Clears the display, like `00E0`.
+fn clear(chip8: &mut Chip8) {
+    chip8.display.fill(0);
+    chip8.draw_flag = true;
+}
+
+pub fn routine(name: &str) -> Option<Routine> {
+    ROUTINES.iter().find(|(routine, _)| *routine == name).map(|(_, routine)| *routine)
+}
+
+/// Why the emulation can't go on, with the address of the instruction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    UnknownOpcode { opcode: u16, address: u16 },
+    /// A `0NNN` call to the machine code routine at `routine`.
+    MachineCode { routine: u16, address: u16 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode { opcode, address } => write!(f, "unknown opcode {:04X} at 0x{:03X}", opcode, address),
+            Error::MachineCode { routine, address } => {
+                write!(f, "call to the machine code routine at 0x{:03X} from 0x{:03X}", routine, address)
+            }
+        }
+    }
+}
+
+/// How much of its intensity a pixel keeps at every vertical blank once turned off, in `Decay` mode.
+const DECAY: f32 = 0.6;
+
+/// SHA-1 of a program, as used by the ROM database.
+pub fn hash_program(buffer: &[u8]) -> String {
+    sha1_smol::Sha1::from(buffer).hexdigest()
+}
+
+/// A copy of the registers, for debuggers and front-ends to show.
+#[derive(Clone, Copy, Default)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u32,
+    pub pc: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// The register `FX0A` is waiting to store a key in.
+    pub waiting_for_key: Option<u8>,
+}
+
+/// An `FX0A` waiting for a key. Keys already held when it started
+/// have to be let go and pressed again, so a key doesn't count twice.
+#[derive(Clone, Copy)]
+struct KeyWait {
+    register: u8,
+    pressed: [bool; 16],   // went down during the wait
+    released: Option<u8>, // the first of those to be let go
+}
 
 /// **CHIP-8**
 /// System memory
 /// `0x000-0x1FF` Chip 8 interpreter (contains font set in emu)
-/// `0x050-0x0A0` Used for the built in 4x5 pixel font set (0-F)
+/// `0x050-0x140` Used for the built in 4x5 and 8x10 pixel font sets (0-F), see `Font`
 /// `0x200-0xFFF` Program ROM and work RAM
+/// Other platforms move the program and end the memory elsewhere, see `Platform`.
 pub struct Chip8 {
-    opcode: u16,                        // 2-byte (16-bit)
-    memory: [u8; MEMORY_SIZE as usize], // 1-byte (8-bit)
-    v: [u8; 16],                        // Registers, from V0 to VF
-    i: u16,                             // index register
-    pc: u16,                            // program counter
+    opcode: u16,     // 2-byte (16-bit)
+    memory: Vec<u8>, // 1-byte (8-bit)
+    v: [u8; 16],     // Registers, from V0 to VF
+    i: u32,          // index register, 24 bits with MegaChip
+    pc: u16,         // program counter
+
+    width: usize,     // of the display, in pixels
+    height: usize,
+    display: Vec<u8>, // the planes every pixel is lit on, one bit each
+    planes: u8,       // XO-CHIP planes `00E0` and `DXYN` work on, see `FN01`
+    second_plane: bool, // the second plane was selected since the start
+
+    platform: Platform,                    // which instructions there are
+    background: u8,                        // CHIP-8X background colour, see `CHIP8X_BACKGROUNDS`
+    zones: [u8; ZONE_COLUMNS * ZONE_ROWS], // CHIP-8X colour of the lit pixels, see `CHIP8X_COLORS`
+    megachip: Option<MegaChip>,            // MegaChip mode, with its colour display
 
-    display: [u8; SCREEN_SIZE as usize], // black or white
+    display_mode: DisplayMode,
+    output: Vec<u8>,    // what the front-ends show, see `DisplayMode`
+    previous: Vec<u8>,  // the display at the last vertical blank
+    intensity: Vec<u8>, // brightness of every pixel of `output`, in `Decay` mode
 
     delay_timer: u8, // Time registers (60HZ)
     sound_timer: u8, // When set above zero they'll count down to zero
@@ -47,7 +516,29 @@ pub struct Chip8 {
     stack: [u16; 16], // 16 levels of stack
     sp: u8,           // stack pointer
 
-    key: [u8; 17], // HEX based keypad (0x0-0x0F) + [0x10] = `don't emulate cycles until a key is pressed!`
+    key: [u8; 16],              // HEX based keypad (0x0-0xF)
+    second_key: [u8; 16],       // the second keypad of CHIP-8X
+    key_wait: Option<KeyWait>, // `FX0A` blocking until a key is pressed or released
+
+    start: u16,     // where programs are loaded and executed from
+    font: Font,      // the glyphs of `FX29` and `FX30`
+    font_address: u16, // where the font is kept in memory
+    current: u16,   // address of the instruction being executed
+    diagnostics: Diagnostics,
+    initialized: Vec<bool>,                    // loaded or written since the start
+    executed: Vec<bool>,                       // fetched as part of an instruction
+    warned: BTreeSet<String>,                  // warnings already printed
+    halt: Option<Halt>,                        // a diagnostic breaking once the instruction is done
+    quirks: Quirks, // behaviours that differ between interpreters
+    rng: StdRng,    // source for `RND`, seedable to make runs reproducible
+    rom_hash: String, // SHA-1 of the loaded program, identifies the game
+
+    machine_code: MachineCode,         // what `0NNN` does
+    routines: BTreeMap<u16, Routine>, // host versions of the routines `0NNN` calls, by address
+
+    timing: Timing, // how many instructions a frame runs
+    cycles: i32,    // VIP machine cycles left in this frame, negative when the last instruction ran over
+    vblank: bool,   // no instruction ran since the vertical blank, `DXYN` can draw
 
     // Implementation flags,
     // draw_flag: makes sure the backend draws the current display array to the screen
@@ -56,14 +547,30 @@ pub struct Chip8 {
 
 impl Chip8 {
     pub fn new() -> Self {
+        let memory_size = Platform::Chip8.memory_size();
+        let (width, height) = Platform::Chip8.display();
         Self {
             opcode: 0,
-            memory: [0; MEMORY_SIZE as usize],
+            memory: vec![0; memory_size],
             v: [0; 16],
             i: 0,
             pc: 0,
 
-            display: [0; SCREEN_SIZE as usize],
+            width,
+            height,
+            display: vec![0; width * height],
+            planes: 1,
+            second_plane: false,
+
+            platform: Platform::Chip8,
+            background: 0,
+            zones: [1; ZONE_COLUMNS * ZONE_ROWS],
+            megachip: None,
+
+            display_mode: DisplayMode::Immediate,
+            output: vec![0; width * height],
+            previous: vec![0; width * height],
+            intensity: vec![0; width * height],
 
             delay_timer: 0,
             sound_timer: 0,
@@ -71,58 +578,173 @@ impl Chip8 {
             stack: [0; 16],
             sp: 0,
 
-            key: [0; 17],
+            key: [0; 16],
+            second_key: [0; 16],
+            key_wait: None,
+
+            start: 0x200,
+            font: Font::default(),
+            font_address: FONT_ADDRESS,
+            current: 0,
+            diagnostics: Diagnostics::default(),
+            initialized: vec![false; memory_size],
+            executed: vec![false; memory_size],
+            warned: BTreeSet::new(),
+            halt: None,
+            quirks: Quirks::default(),
+            rng: StdRng::seed_from_u64(rand::random()),
+            rom_hash: String::new(),
+
+            machine_code: MachineCode::Error,
+            routines: BTreeMap::new(),
+
+            timing: Timing::Fixed,
+            cycles: 0,
+            vblank: false,
 
             draw_flag: false,
         }
     }
 
+    /// Sizes the memory and the display like `platform`, before `initialize`.
+    pub fn set_platform(&mut self, platform: Platform) {
+        let memory_size = platform.memory_size();
+        let (width, height) = platform.display();
+        self.platform = platform;
+        self.memory = vec![0; memory_size];
+        self.initialized = vec![false; memory_size];
+        self.executed = vec![false; memory_size];
+        self.resize_display(width, height);
+    }
+
+    fn resize_display(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.display = vec![0; width * height];
+        self.output = vec![0; width * height];
+        self.previous = vec![0; width * height];
+        self.intensity = vec![0; width * height];
+        self.draw_flag = true;
+    }
+
+    /// `0011` turns MegaChip mode on, with its 256x192 display, `0010` off.
+    fn set_megachip(&mut self, enabled: bool) {
+        let (width, height) = if enabled {
+            self.megachip = Some(MegaChip::new());
+            (MEGACHIP_WIDTH, MEGACHIP_HEIGHT)
+        } else {
+            self.megachip = None;
+            self.platform.display()
+        };
+        self.resize_display(width, height);
+    }
+
     /// Normally starts at 0x200
     pub fn initialize(&mut self, start: u16) {
         //initializing default values
+        self.start = start;
         self.pc = start;
         self.opcode = 0;
         self.i = 0;
         self.sp = 0;
-        self.display = [0; SCREEN_SIZE as usize];
+        self.display.fill(0);
+        self.planes = 1;
+        self.second_plane = false;
+        self.output.fill(0);
+        self.previous.fill(0);
+        self.intensity.fill(0);
         self.stack = [0; 16];
-        self.key = [0; 17];
+        self.key = [0; 16];
+        self.second_key = [0; 16];
+        self.key_wait = None;
+        self.background = 0;
+        self.zones = [1; ZONE_COLUMNS * ZONE_ROWS];
+        if self.megachip.is_some() {
+            self.set_megachip(false);
+        }
         self.v = [0; 16];
-        self.memory = [0; MEMORY_SIZE as usize];
+        self.memory.fill(0);
 
-        //loading font set
-        for i in 0..FONT_NUMBER {
-            self.memory[i] = FONTSET[i];
-        }
+        self.initialized.fill(false);
+        self.load_font();
+        self.executed.fill(false);
+        self.warned.clear();
+        self.halt = None;
 
         //reset timers
         self.delay_timer = 0;
         self.sound_timer = 0;
 
+        self.cycles = 0;
+        self.vblank = false;
         self.draw_flag = false;
     }
 
-    pub fn get_display(&self) -> &[u8; SCREEN_SIZE as usize] {
-        &self.display
+    pub fn set_diagnostics(&mut self, diagnostics: Diagnostics) {
+        self.diagnostics = diagnostics;
     }
 
-    pub fn set_key(&mut self, key: u8, state: u8) {
-        self.key[key as usize] = state;
+    /// Replaces the font and moves it to `address`, before the program is loaded.
+    pub fn set_font(&mut self, font: Font, address: u16) {
+        let (old, size) = (self.font_address as usize, self.font.size());
+        self.memory[old..old + size].fill(0);
+        self.initialized[old..old + size].fill(false);
+        self.font = font;
+        self.font_address = address;
+        self.load_font();
     }
 
-    pub fn load_program(&mut self, buffer: Vec<u8>) {
-        // if program is bigger than memory, display error
-        let program_size = buffer.len();
-        if program_size >= (MEMORY_SIZE - 512) as usize {
-            panic!("Program size is bigger than current memory!");
-        }
+    fn load_font(&mut self) {
+        let small = self.font_address as usize;
+        let big = small + self.font.small.len();
+        self.memory[small..big].copy_from_slice(&self.font.small);
+        self.memory[big..big + self.font.big.len()].copy_from_slice(&self.font.big);
+        self.initialized[small..big + self.font.big.len()].fill(true);
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Makes `RND` return the same sequence on every run.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// `routines` are only used with `MachineCode::Native`.
+    pub fn set_machine_code(&mut self, machine_code: MachineCode, routines: BTreeMap<u16, Routine>) {
+        self.machine_code = machine_code;
+        self.routines = routines;
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
 
-        for i in 0..program_size {
-            // println!("Memory[{}] = {:x}", i + 0x200, buffer[i]);
-            self.memory[i + 0x200] = buffer[i];
+    pub fn set_display_mode(&mut self, mode: DisplayMode) {
+        self.display_mode = mode;
+    }
+
+    /// Fails when the program doesn't fit in memory from the start address.
+    pub fn load_program(&mut self, buffer: Vec<u8>) -> Result<(), String> {
+        let program_size = buffer.len();
+        let start = self.start as usize;
+        let space = self
+            .memory
+            .len()
+            .checked_sub(start)
+            .ok_or_else(|| format!("the start 0x{:03X} is past the end of memory", start))?;
+        if program_size > space {
+            return Err(format!(
+                "the program is {} bytes, only {} fit in memory from 0x{:03X}",
+                program_size, space, start
+            ));
         }
 
-        println!("Loaded program from {} to {}", 0x200, 0x200 + program_size);
+        self.memory[start..start + program_size].copy_from_slice(&buffer);
+        self.initialized[start..start + program_size].fill(true);
+        self.rom_hash = hash_program(&buffer);
+        Ok(())
     }
 
     /// Fetches one opcode from the memory at the location specified by the PC (program counter)
@@ -149,12 +771,67 @@ impl Chip8 {
     ///1010001011110000   // 0xA2F0
     ///```
     pub fn decode_opcode(&mut self) -> u16 {
-        println!(
-            "PC: {} Memory: {:04X}",
-            self.pc,
-            (self.memory[self.pc as usize] as u16) << 8 | self.memory[self.pc as usize + 1] as u16
-        );
-        (self.memory[self.pc as usize] as u16) << 8 | self.memory[self.pc as usize + 1] as u16
+        let (high, low) = (self.pc, self.pc.wrapping_add(1));
+        let opcode = (self.read(high as u32) as u16) << 8 | self.read(low as u32) as u16;
+        let size = self.memory.len();
+        self.executed[high as usize % size] = true;
+        self.executed[low as usize % size] = true;
+        opcode
+    }
+
+    /// Reads memory, wrapping around past the end.
+    fn read(&mut self, address: u32) -> u8 {
+        let address = self.check_bounds(address);
+        if !self.initialized[address] {
+            self.diagnose(self.diagnostics.uninitialized_read, || {
+                format!("read of uninitialised memory at 0x{:03X}", address)
+            });
+        }
+        self.memory[address]
+    }
+
+    /// Writes memory, wrapping around past the end.
+    fn write(&mut self, address: u32, value: u8) {
+        let address = self.check_bounds(address);
+        if address < self.start as usize {
+            self.diagnose(self.diagnostics.protected_write, || {
+                format!("write to the interpreter area at 0x{:03X}", address)
+            });
+        }
+        if self.executed[address] {
+            self.diagnose(self.diagnostics.self_modifying, || format!("write to code at 0x{:03X}", address));
+        }
+        self.memory[address] = value;
+        self.initialized[address] = true;
+    }
+
+    fn check_bounds(&mut self, address: u32) -> usize {
+        let size = self.memory.len();
+        if address as usize >= size {
+            self.diagnose(self.diagnostics.memory_bounds, || {
+                format!("access to 0x{:X}, past the end of memory", address)
+            });
+        }
+        address as usize % size
+    }
+
+    /// Reports what the instruction being executed did, according to `level`.
+    fn diagnose(&mut self, level: Level, message: impl FnOnce() -> String) {
+        if level == Level::Ignore {
+            return;
+        }
+        let message = format!("{} by the instruction at 0x{:03X}", message(), self.current);
+        match level {
+            Level::Ignore => (),
+            Level::Warn => {
+                if self.warned.insert(message.clone()) {
+                    eprintln!("Warning: {}", message);
+                }
+            }
+            Level::Break => {
+                self.halt.get_or_insert(Halt::Break(message));
+            }
+        }
     }
 
     /// When executing a opcode, there are common parts used to store the metadata
@@ -167,7 +844,7 @@ impl Chip8 {
     /// y - A 4-bit value, the upper 4 bits of the low byte of the instruction
     /// kk or byte - An 8-bit value, the lowest 8 bits of the instruction
     ///```
-    pub fn execute_opcode(&mut self) {
+    pub fn execute_opcode(&mut self) -> Result<(), Halt> {
         let nnn = self.opcode & 0x0FFF;
         let n = (self.opcode & 0x000F) as u8;
         let x = (self.opcode & 0x0F00) >> 8;
@@ -175,19 +852,45 @@ impl Chip8 {
         let kk = (self.opcode & 0x00FF) as u8;
 
         match self.opcode {
+            0x00E0 if self.megachip.is_some() => {
+                // [MegaChip] Show what was drawn since the last time, then clear it.
+                let megachip = self.megachip.as_mut().expect("Not in MegaChip mode!");
+                let alpha = Blend::Opacity(megachip.alpha);
+                for (shown, drawn) in megachip.screen.iter_mut().zip(&megachip.buffer) {
+                    *shown = alpha.mix((0, 0, 0), *drawn);
+                }
+                megachip.buffer.fill((0, 0, 0));
+                megachip.indices.fill(0);
+                self.draw_flag = true;
+            }
             0x00E0 => {
                 // println!("CLEAR!");
-                self.display = [0; SCREEN_SIZE as usize];
+                // only the selected planes, XO-CHIP programs clear them one by one
+                for pixel in self.display.iter_mut() {
+                    *pixel &= !self.planes;
+                }
                 self.draw_flag = true;
             }
             0x00EE => {
                 // [RET] Return from a subroutine.
                 // println!("BEFORE -> PC: {} SP: {} Stack: {:?}", self.pc, self.sp, self.stack);
+                if self.sp == 0 {
+                    self.diagnose(self.diagnostics.stack_underflow, || "stack underflow".to_string());
+                    self.sp = self.stack.len() as u8;
+                }
                 self.sp -= 1;
                 self.pc = self.stack[self.sp as usize];
                 // println!("AFTER -> PC: {} SP: {} Stack: {:?}", self.pc, self.sp, self.stack);
                 // self.pc -= 2;
             }
+            0x02A0 if self.platform == Platform::Chip8x => {
+                // [CHIP-8X] Step the background to its next colour.
+                self.background = (self.background + 1) % CHIP8X_BACKGROUNDS.len() as u8;
+                self.draw_flag = true;
+            }
+            0x0010 | 0x0011 if self.platform == Platform::MegaChip => self.set_megachip(self.opcode == 0x0011),
+            0x0100..=0x09FF if self.megachip.is_some() => return self.execute_megachip(),
+            0x0000..=0x0FFF => return self.machine_code(nnn),
             0x1000..=0x1FFF => {
                 // [JP addr] Jump to location nnn.
                 self.pc = nnn;
@@ -196,6 +899,10 @@ impl Chip8 {
             0x2000..=0x2FFF => {
                 // [CALL addr] Call subroutine at nnn.
                 // println!("BEFORE -> PC: {} SP: {} Stack: {:?}", self.pc, self.sp, self.stack);
+                if self.sp as usize == self.stack.len() {
+                    self.diagnose(self.diagnostics.stack_overflow, || "stack overflow".to_string());
+                    self.sp = 0;
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = nnn;
@@ -214,7 +921,12 @@ impl Chip8 {
                     self.pc += 2
                 }
             }
-            0x5000..=0x5FFF => {
+            0x5001..=0x5FFF if self.platform == Platform::Chip8x && n == 1 => {
+                // [CHIP-8X] Add Vy to Vx, both nibbles on their own and modulo 8, to mix colours.
+                let (vx, vy) = (self.v[x as usize], self.v[y as usize]);
+                self.v[x as usize] = ((vx & 0x70) + (vy & 0x70)) & 0x70 | ((vx & 0x07) + (vy & 0x07)) & 0x07;
+            }
+            0x5000..=0x5FFF if n == 0 => {
                 // [SE Vx, Vy] Skip next instruction if Vx = Vy.
                 if self.v[x as usize] == self.v[y as usize] {
                     self.pc += 2
@@ -226,7 +938,7 @@ impl Chip8 {
             }
             0x7000..=0x7FFF => {
                 // [ADD Vx, byte] Set Vx = Vx + kk.
-                self.v[x as usize] += kk;
+                self.v[x as usize] = self.v[x as usize].wrapping_add(kk);
             }
             0x8000..=0x8FFF => {
                 match n{
@@ -236,17 +948,24 @@ impl Chip8 {
                     }
                     0x1 => {
                         // [OR Vx, Vy] Set Vx = Vx OR Vy.
-                        println!("BEFORE -> VX[{:x}] = {} VY[{:x}] = {}", x, self.v[x as usize], y, self.v[y as usize]);
                         self.v[x as usize] |= self.v[y as usize];
-                        println!("AFTER -> VX[{:x}] = {} VY[{:x}] = {}", x, self.v[x as usize], y, self.v[y as usize]);
+                        if self.quirks.vf_reset {
+                            self.v[0xF] = 0;
+                        }
                     }
                     0x2 => {
                         // [AND Vx, Vy] Set Vx = Vx AND Vy.
                         self.v[x as usize] &= self.v[y as usize];
+                        if self.quirks.vf_reset {
+                            self.v[0xF] = 0;
+                        }
                     }
                     0x3 => {
                         // [XOR Vx, Vy] Set Vx = Vx XOR Vy.
                         self.v[x as usize] ^= self.v[y as usize];
+                        if self.quirks.vf_reset {
+                            self.v[0xF] = 0;
+                        }
                     }
                     0x4 => {
                         // [ADD Vx, Vy] Set Vx = Vx + Vy, set VF = carry.
@@ -266,10 +985,13 @@ impl Chip8 {
                         } else {
                             self.v[0xF] = 0
                         }
-                        self.v[x as usize] -= self.v[y as usize];
+                        self.v[x as usize] = self.v[x as usize].wrapping_sub(self.v[y as usize]);
                     }
                     0x6 => {
                         // [SHR Vx {, Vy}] Set Vx = Vx SHR 1.
+                        if self.quirks.shift_vy {
+                            self.v[x as usize] = self.v[y as usize];
+                        }
                         self.v[0xF] = self.v[x as usize] & 0x1;
                         self.v[x as usize] >>= 1;
                     }
@@ -280,19 +1002,17 @@ impl Chip8 {
                         } else {
                             self.v[0xF] = 0
                         }
-                        self.v[x as usize] = self.v[y as usize] - self.v[x as usize];
+                        self.v[x as usize] = self.v[y as usize].wrapping_sub(self.v[x as usize]);
                     }
                     0xE => {
                         // [SHL Vx {, Vy}] Set Vx = Vx SHL 1.
-                        let most = (self.v[x as usize] & 0xF0) >> 4;
-                        if most == 1 {
-                            self.v[0xF] = 1
-                        } else {
-                            self.v[0xF] = 0
+                        if self.quirks.shift_vy {
+                            self.v[x as usize] = self.v[y as usize];
                         }
+                        self.v[0xF] = self.v[x as usize] >> 7;
                         self.v[x as usize] <<= 1;
                     }
-                    _ => panic!("Unknown opcode: {:x?}", self.opcode)
+                    _ => return Err(self.unknown_opcode())
                 }
             }
             0x9000..=0x9FF0 => {
@@ -303,73 +1023,121 @@ impl Chip8 {
             }
             0xA000..=0xAFFF => {
                 // [LD I, addr] Set I = nnn.
-                self.i = nnn;
+                self.i = nnn as u32;
             }
+            0xB000..=0xBFFF if self.platform == Platform::Chip8x => self.set_zone_colors(x, y, n),
             0xB000..=0xBFFF => {
                 // [JP V0, addr] Jump to location nnn + V0.
-                self.pc = nnn + self.v[0x0] as u16;
+                if self.quirks.jump_vx {
+                    self.pc = nnn + self.v[x as usize] as u16;
+                } else {
+                    self.pc = nnn + self.v[0x0] as u16;
+                }
             }
             0xC000..=0xCFFF => {
                 // [RND Vx, byte] Set Vx = random byte AND kk.
-                let random = rand::thread_rng().gen::<u8>();
+                let random = self.rng.gen::<u8>();
                 self.v[x as usize] = kk & random;
             }
+            0xD000..=0xDFFF if self.megachip.is_some() => self.draw_megachip(x, y, n),
             0xD000..=0xDFFF => {
-                self.v[0xF] = 0;
-
-                let x_pos = self.v[x as usize] as u16 % SCREEN_WIDTH;
-                let y_pos = self.v[y as usize] as u16 % SCREEN_HEIGHT;
+                // [DRW Vx, Vy, nibble] Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+                // The sprite starts on the screen, then either goes off its edges or wraps around.
+                // With both XO-CHIP planes selected, the rows of the second plane follow those of the first.
+                let (width, height) = (self.width as u16, self.height as u16);
+                let x_pos = self.v[x as usize] as u16 % width;
+                let y_pos = self.v[y as usize] as u16 % height;
 
-                for h in 0..n {
-                    let row = self.memory[(self.i + h as u16) as usize];
+                let mut collision = false;
+                let mut rows = 0; // colliding or clipped at the bottom
+                let mut address = self.i;
+                let planes = self.planes;
+                for plane in [1, 2].iter().copied().filter(|plane| planes & plane != 0) {
+                    for h in 0..n as u16 {
+                        if y_pos + h >= height && self.quirks.clip_sprites {
+                            rows += 1;
+                            continue;
+                        }
+                        let row_y = (y_pos + h) % height;
+                        let row = self.read(address.wrapping_add(h as u32));
 
-                    for w in 0..8 {
-                        let index = (y_pos + h as u16) * 64 + (x_pos + w as u16);
-                        if row & (0x80 >> w) != 0 {
-                            if self.display[index as usize] == 1 {
-                                self.v[0xF] = 1;
+                        let mut row_collision = false;
+                        for w in 0..8 {
+                            if row & (0x80 >> w) == 0 || (x_pos + w >= width && self.quirks.clip_sprites) {
+                                continue;
                             }
-                            self.display[index as usize] ^= 1;
+                            let index = (row_y * width + (x_pos + w) % width) as usize;
+                            row_collision |= self.display[index] & plane != 0;
+                            self.display[index] ^= plane;
                         }
+                        collision |= row_collision;
+                        rows += row_collision as u8;
                     }
+                    address = address.wrapping_add(n as u32);
                 }
+
+                self.v[0xF] = if self.quirks.vf_row_count { rows } else { collision as u8 };
                 self.draw_flag = true;
             }
             0xE000..=0xEFFF => {
                 match kk{
                     0x9E => {
                         // [SKP Vx] Skip next instruction if key with the value of Vx is pressed.
-                        if self.key[self.v[x as usize] as usize] != 0 {
+                        if self.key[(self.v[x as usize] & 0xF) as usize] != 0 {
                             self.pc += 2
                         }
                     }
                     0xA1 => {
                         // [SKNP Vx] Skip next instruction if key with the value of Vx is not pressed.
-                        if self.key[self.v[x as usize] as usize] == 0 {
+                        if self.key[(self.v[x as usize] & 0xF) as usize] == 0 {
                             self.pc += 2
                         }
                     }
-                    _ => panic!("Unknown opcode: {:x?}", self.opcode)
+                    0xF2 if self.platform == Platform::Chip8x => {
+                        // [CHIP-8X] Skip next instruction if key Vx of the second keypad is pressed.
+                        if self.second_key[(self.v[x as usize] & 0xF) as usize] != 0 {
+                            self.pc += 2
+                        }
+                    }
+                    0xF5 if self.platform == Platform::Chip8x => {
+                        // [CHIP-8X] Skip next instruction if key Vx of the second keypad is not pressed.
+                        if self.second_key[(self.v[x as usize] & 0xF) as usize] == 0 {
+                            self.pc += 2
+                        }
+                    }
+                    _ => return Err(self.unknown_opcode())
                 }
             }
             0xF000..=0xFFFF =>{
                 match kk{
+                    0x01 => {
+                        // [PLANE n] Select the planes `00E0` and `DXYN` work on, 0 to 3 (XO-CHIP).
+                        self.planes = x as u8 & 0x3;
+                        self.second_plane |= self.planes & 0x2 != 0;
+                    }
                     0x07 => {
                         // [LD Vx, DT] Set Vx = delay timer value.
                         self.v[x as usize] = self.delay_timer;
                     }
                     0x0A => {
                         // [LD Vx, K] Wait for a key press, store the value of the key in Vx.
-                        let mut got_key = false;
-                        for i in 0..0xF{
-                            if self.key[i as usize] != 0{
-                                self.v[x as usize] = i;
-                                got_key = true;
-                                break;
+                        // Runs again and again until a key comes, the timers going on meanwhile.
+                        let wait = self.key_wait.get_or_insert(KeyWait {
+                            register: x as u8,
+                            pressed: [false; 16],
+                            released: None,
+                        });
+                        let key = if self.quirks.key_release {
+                            wait.released
+                        } else {
+                            wait.pressed.iter().position(|pressed| *pressed).map(|key| key as u8)
+                        };
+                        match key {
+                            Some(key) => {
+                                self.v[x as usize] = key;
+                                self.key_wait = None;
                             }
-                        }
-                        if !got_key{
-                            self.pc -= 2;
+                            None => self.pc = self.pc.wrapping_sub(2),
                         }
                     }
                     0x15 => {
@@ -382,56 +1150,776 @@ impl Chip8 {
                     }
                     0x1E => {
                         // [ADD I, Vx] Set I = I + Vx.
-                        self.i += self.v[x as usize] as u16;
+                        self.i = self.i.wrapping_add(self.v[x as usize] as u32);
                     }
                     0x29 => {
                         // [LD F, Vx] Set I = location of sprite for digit Vx.
-                        self.i = self.v[x as usize] as u16 * 5; //sprites are 5-byte long
+                        let digit = (self.v[x as usize] & 0xF) as usize;
+                        self.i = (self.font_address as usize + digit * SMALL_GLYPH) as u32;
+                    }
+                    0x30 => {
+                        // [LD HF, Vx] Set I = location of the big sprite for digit Vx (SUPER-CHIP).
+                        let digit = (self.v[x as usize] & 0xF) as usize;
+                        self.i = (self.font_address as usize + self.font.small.len() + digit * BIG_GLYPH) as u32;
                     }
                     0x33 => {
                         // [LD B, Vx] Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                        self.memory[self.i as usize + 0] =  self.v[x as usize] / 100;
-                        self.memory[self.i as usize + 1] = (self.v[x as usize] / 10) % 10;
-                        self.memory[self.i as usize + 2] =  self.v[x as usize] % 10;
+                        let value = self.v[x as usize];
+                        self.write(self.i, value / 100);
+                        self.write(self.i.wrapping_add(1), (value / 10) % 10);
+                        self.write(self.i.wrapping_add(2), value % 10);
                     }
                     0x55 => {
                         // [LD [I], Vx] Store registers V0 through Vx in memory starting at location I.
                         for i in 0..x as usize+1{
-                            self.memory[self.i as usize + i] = self.v[i];
+                            self.write(self.i.wrapping_add(i as u32), self.v[i]);
                         }
 
-                        self.i += x + 1;
+                        if self.quirks.load_store_increment {
+                            self.i = self.i.wrapping_add(x as u32 + 1);
+                        }
                     }
                     0x65 => {
                         // [LD Vx, [I]] Read registers V0 through Vx from memory starting at location I.
                         for i in 0..x as usize+1{
-                            self.v[i] = self.memory[self.i as usize + i];
+                            self.v[i] = self.read(self.i.wrapping_add(i as u32));
+                        }
+                        if self.quirks.load_store_increment {
+                            self.i = self.i.wrapping_add(x as u32 + 1);
                         }
-                        self.i += x + 1;
                     }
-                    _ => panic!("Unknown opcode: {:x?}", self.opcode)
+                    _ => return Err(self.unknown_opcode())
+                }
+            }
+            _ => return Err(self.unknown_opcode())
+        }
+        Ok(())
+    }
+
+    fn unknown_opcode(&self) -> Halt {
+        Halt::Error(Error::UnknownOpcode {
+            opcode: self.opcode,
+            address: self.current,
+        })
+    }
+
+    /// The MegaChip instructions from `0100` to `09FF`.
+    fn execute_megachip(&mut self) -> Result<(), Halt> {
+        let nn = (self.opcode & 0x00FF) as usize;
+        match self.opcode & 0xFF00 {
+            0x0100 => {
+                // [LDHI I, nnnnnn] Set I to NN and the 16 bits of the next word.
+                let pc = self.pc as u32;
+                let low = (self.read(pc) as u32) << 8 | self.read(pc + 1) as u32;
+                self.pc = self.pc.wrapping_add(2);
+                self.i = (nn as u32) << 16 | low;
+            }
+            0x0200 => {
+                // [LDPAL nn] Load NN colours from I, in ARGB, into the palette from index 1.
+                let mut colors = Vec::with_capacity(nn);
+                for n in 0..nn as u32 {
+                    let argb: Vec<u8> = (1..4).map(|byte| self.read(self.i.wrapping_add(n * 4 + byte))).collect();
+                    colors.push((argb[0], argb[1], argb[2]));
                 }
+                let megachip = self.megachip.as_mut().expect("Not in MegaChip mode!");
+                megachip.palette[1..=nn].copy_from_slice(&colors);
+            }
+            // [SPRW nn], [SPRH nn] Set the size of the sprites, 0 being 256.
+            0x0300 => self.megachip.as_mut().expect("Not in MegaChip mode!").sprite_width = nn,
+            0x0400 => self.megachip.as_mut().expect("Not in MegaChip mode!").sprite_height = nn,
+            // [ALPHA nn] Set the opacity of the whole screen.
+            0x0500 => self.megachip.as_mut().expect("Not in MegaChip mode!").alpha = nn as u8,
+            0x0600 if nn <= 1 => {
+                // [DIGISND n] Play the sound at I, over and over unless N is 1.
+                let header: Vec<u8> = (0..6).map(|byte| self.read(self.i.wrapping_add(byte))).collect();
+                let megachip = self.megachip.as_mut().expect("Not in MegaChip mode!");
+                megachip.sample = Some(Sample {
+                    start: self.i.wrapping_add(6),
+                    rate: (header[0] as u32) << 8 | header[1] as u32,
+                    length: (header[2] as u32) << 16 | (header[3] as u32) << 8 | header[4] as u32,
+                    looping: nn == 0,
+                    played: 0,
+                });
             }
-            _ => panic!("Unknown opcode: {:x?}", self.opcode)
+            // [STOPSND] Stop the sound.
+            0x0700 if nn == 0 => self.megachip.as_mut().expect("Not in MegaChip mode!").sample = None,
+            0x0800 if nn <= 5 => {
+                // [BMODE n] How sprites are mixed with what's under them.
+                self.megachip.as_mut().expect("Not in MegaChip mode!").blend = match nn {
+                    0 => Blend::Normal,
+                    1 => Blend::Opacity(0x40),
+                    2 => Blend::Opacity(0x80),
+                    3 => Blend::Opacity(0xC0),
+                    4 => Blend::Add,
+                    _ => Blend::Multiply,
+                };
+            }
+            // [CCOL nn] Set the colour `DXYN` collides with.
+            0x0900 => self.megachip.as_mut().expect("Not in MegaChip mode!").collision_color = nn as u8,
+            _ => return Err(self.unknown_opcode()),
         }
+        Ok(())
     }
 
-    pub fn emulate_cycle(&mut self) {
+    /// MegaChip `DXYN`: draws a sprite of the size set by `03NN` and `04NN`, one byte
+    /// per pixel indexing the palette, 0 being transparent. The font stays 1 bit per
+    /// pixel, N rows of 8 pixels in white. Sprites are cut off at the edges of the screen.
+    fn draw_megachip(&mut self, x: u16, y: u16, n: u8) {
+        let (vx, vy) = (self.v[x as usize] as usize, self.v[y as usize] as usize);
+        let font = self.font_address as u32..self.font_address as u32 + self.font.size() as u32;
+        let megachip = self.megachip.as_ref().expect("Not in MegaChip mode!");
+        let (width, height) = match font.contains(&self.i) {
+            true => (8, n as usize),
+            false if megachip.sprite_width == 0 => (256, megachip.sprite_height),
+            false => (megachip.sprite_width, megachip.sprite_height),
+        };
+        let height = if height == 0 { 256 } else { height };
+
+        let mut sprite = Vec::with_capacity(width * height);
+        for row in 0..height as u32 {
+            if font.contains(&self.i) {
+                let byte = self.read(self.i.wrapping_add(row));
+                sprite.extend((0..8).map(|bit| if byte & (0x80 >> bit) != 0 { 0xFF } else { 0 }));
+            } else {
+                for column in 0..width as u32 {
+                    sprite.push(self.read(self.i.wrapping_add(row * width as u32 + column)));
+                }
+            }
+        }
+
+        let megachip = self.megachip.as_mut().expect("Not in MegaChip mode!");
+        let white = font.contains(&self.i);
+        let mut collision = false;
+        for (n, index) in sprite.into_iter().enumerate() {
+            let (px, py) = (vx + n % width, vy + n / width);
+            if index == 0 || px >= MEGACHIP_WIDTH || py >= MEGACHIP_HEIGHT {
+                continue;
+            }
+            let pixel = py * MEGACHIP_WIDTH + px;
+            // blank pixels don't count, whatever the collision colour
+            collision |= megachip.indices[pixel] != 0 && megachip.indices[pixel] == megachip.collision_color;
+            let color = if white { (0xFF, 0xFF, 0xFF) } else { megachip.palette[index as usize] };
+            megachip.buffer[pixel] = megachip.blend.mix(megachip.buffer[pixel], color);
+            megachip.indices[pixel] = index;
+        }
+        self.v[0xF] = collision as u8;
+    }
+
+    /// CHIP-8X `BXYN`: sets the colour in `V(X+1)` of a part of the display.
+    /// `BXY0` colours whole blocks of 8x4 pixels, the low nibbles of Vx and Vy
+    /// being the first block and the high nibbles how many more there are.
+    /// Otherwise the zones under an 8xN sprite drawn at (Vx, Vy) are coloured, row by row.
+    fn set_zone_colors(&mut self, x: u16, y: u16, n: u8) {
+        let (vx, vy) = (self.v[x as usize] as usize, self.v[y as usize] as usize);
+        let color = self.v[(x as usize + 1) % 16] & 0x7;
+        let (columns, rows) = if n == 0 {
+            let rows = ZONE_ROWS / 8;
+            let columns = (vx & 0xF)..=(vx & 0xF) + (vx >> 4);
+            (columns, (vy & 0xF) * rows..((vy & 0xF) + (vy >> 4) + 1) * rows)
+        } else {
+            let column = vx / 8 % ZONE_COLUMNS;
+            (column..=column, vy % ZONE_ROWS..vy % ZONE_ROWS + n as usize)
+        };
+        for row in rows.filter(|row| *row < ZONE_ROWS) {
+            for column in columns.clone().filter(|column| *column < ZONE_COLUMNS) {
+                self.zones[row * ZONE_COLUMNS + column] = color;
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    /// `0NNN`, a call to the machine code at `routine`.
+    fn machine_code(&mut self, routine: u16) -> Result<(), Halt> {
+        let address = self.current;
+        let error = Halt::Error(Error::MachineCode { routine, address });
+        match self.machine_code {
+            MachineCode::Ignore => Ok(()),
+            MachineCode::Break => Err(Halt::Break(Error::MachineCode { routine, address }.to_string())),
+            MachineCode::Error => Err(error),
+            MachineCode::Native => {
+                let native = *self.routines.get(&routine).ok_or(error)?;
+                native(self);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn emulate_cycle(&mut self) -> Result<(), Halt> {
+        self.current = self.pc;
         self.opcode = self.decode_opcode();
-        self.pc += 2;
-        self.execute_opcode();
+        self.pc = self.pc.wrapping_add(2);
+        let result = self.execute_opcode();
+
+        if self.draw_flag && self.display_mode == DisplayMode::Immediate {
+            self.output.copy_from_slice(&self.display);
+        }
+        result?;
+        self.halt.take().map_or(Ok(()), Err)
+    }
+
+    /// The machine cycles an instruction takes on the VIP, in the current state,
+    /// approximated from the listing of its interpreter.
+    fn vip_cycles(&self, opcode: u16) -> i32 {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let vx = self.v[x];
+        let vy = self.v[((opcode & 0x00F0) >> 4) as usize];
+        let n = (opcode & 0x000F) as i32;
+        let kk = (opcode & 0x00FF) as u8;
+        let pressed = self.key[(vx & 0xF) as usize] != 0;
+        // skipping costs a little more than going on
+        let skip = |skipped: bool| if skipped { 14 } else { 10 };
+
+        let cycles = match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => 24,
+                0x00EE => 10,
+                _ => 23,
+            },
+            0x1000 => 12,
+            0x2000 => 26,
+            0x3000 => skip(vx == kk),
+            0x4000 => skip(vx != kk),
+            0x5000 => skip(vx == vy) + 4,
+            0x6000 => 6,
+            0x7000 => 10,
+            0x8000 => 44,
+            0x9000 => skip(vx != vy) + 4,
+            0xA000 => 12,
+            0xB000 => 22,
+            0xC000 => 36,
+            // sprite rows not aligned on a byte are shifted across two bytes
+            0xD000 => 26 + n * if vx.is_multiple_of(8) { 34 } else { 46 },
+            0xE000 => skip(pressed == (kk == 0x9E)) + 4,
+            _ => match kk {
+                0x1E => 19,
+                0x29 => 20,
+                // digits are found by repeated subtraction
+                0x33 => 84 + 12 * (vx / 100 + vx / 10 % 10 + vx % 10) as i32,
+                0x55 | 0x65 => 14 + 14 * (x as i32 + 1),
+                _ => 10,
+            },
+        };
+        VIP_FETCH_CYCLES + cycles
+    }
+
+    /// Timers count down at 60Hz, independently of how many cycles run per frame.
+    /// This is the vertical blank too, when the output is updated.
+    pub fn update_timers(&mut self) {
+        self.vertical_blank();
 
-        //update timers
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
 
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEEP!");
+            self.sound_timer -= 1;
+        }
+
+        self.play_sample();
+    }
+
+    /// Plays a frame of the MegaChip sound into `audio`, resampled to `AUDIO_RATE`.
+    fn play_sample(&mut self) {
+        let megachip = match &mut self.megachip {
+            Some(megachip) => megachip,
+            None => return,
+        };
+        megachip.audio.clear();
+        let sample = match &mut megachip.sample {
+            Some(sample) if sample.length > 0 => sample,
+            _ => {
+                megachip.sample = None;
+                return;
             }
+        };
+        let (length, frame) = (sample.length as u64, (AUDIO_RATE / 60) as u64);
+        for n in 0..frame {
+            let position = (sample.played + sample.rate as u64 * n / frame) / 60;
+            if position >= length && !sample.looping {
+                break;
+            }
+            let address = sample.start as u64 + position % length;
+            megachip.audio.push(self.memory[address as usize % self.memory.len()]);
+        }
+        sample.played += sample.rate as u64;
+        if sample.played / 60 >= length {
+            if sample.looping {
+                sample.played %= length * 60;
+            } else {
+                megachip.sample = None;
+            }
+        }
+    }
 
-            self.sound_timer -= 1;
+    fn vertical_blank(&mut self) {
+        match self.display_mode {
+            DisplayMode::Immediate => (),
+            DisplayMode::Vblank => self.output.copy_from_slice(&self.display),
+            DisplayMode::Blend => {
+                for (n, pixel) in self.output.iter_mut().enumerate() {
+                    *pixel = self.display[n] | self.previous[n];
+                }
+            }
+            DisplayMode::Decay => {
+                for (n, pixel) in self.display.iter().enumerate() {
+                    if *pixel != 0 {
+                        self.output[n] = *pixel;
+                        self.intensity[n] = 0xFF;
+                    } else {
+                        self.intensity[n] = (self.intensity[n] as f32 * DECAY) as u8;
+                        if self.intensity[n] == 0 {
+                            self.output[n] = 0;
+                        }
+                    }
+                }
+            }
         }
+        self.previous.copy_from_slice(&self.display);
+        self.draw_flag = false;
+        self.vblank = true;
+    }
+}
+
+impl Machine for Chip8 {
+    /// Runs a 60Hz frame, `speed` instructions or as many as fit in it with
+    /// `Timing::Vip`, then the vertical blank.
+    fn emulate_frame(&mut self, speed: u32) -> Result<(), Halt> {
+        match self.timing {
+            Timing::Fixed => {
+                for _ in 0..speed {
+                    self.emulate_cycle()?;
+                }
+            }
+            Timing::Vip => {
+                self.cycles += VIP_FRAME_CYCLES - VIP_DISPLAY_CYCLES;
+                while self.cycles > 0 {
+                    let opcode = self.next_opcode();
+                    // the interpreter waits for the interrupt before drawing, idling until then
+                    if opcode & 0xF000 == 0xD000 && !self.vblank {
+                        self.cycles = 0;
+                        break;
+                    }
+                    self.cycles -= self.vip_cycles(opcode);
+                    self.vblank = false;
+                    self.emulate_cycle()?;
+                }
+            }
+        }
+        self.update_timers();
+        Ok(())
+    }
+
+    /// What the front-ends should show, according to the `DisplayMode`.
+    fn frame(&self) -> Frame<'_> {
+        Frame {
+            width: self.width,
+            height: self.height,
+            pixels: &self.output,
+            planes: if self.second_plane { 2 } else { 1 },
+            intensity: match self.display_mode {
+                DisplayMode::Decay => Some(&self.intensity),
+                _ => None,
+            },
+            colors: match self.platform {
+                Platform::Chip8x => Some(ColorMap {
+                    background: CHIP8X_BACKGROUNDS[self.background as usize],
+                    zones: &self.zones,
+                    colors: &CHIP8X_COLORS,
+                }),
+                _ => None,
+            },
+            true_color: self.megachip.as_ref().map(|megachip| megachip.screen.as_slice()),
+        }
+    }
+
+    fn registers(&self) -> Registers {
+        Registers {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            waiting_for_key: self.key_wait.map(|wait| wait.register),
+        }
+    }
+
+    /// The opcode the next cycle will execute.
+    fn next_opcode(&self) -> u16 {
+        let pc = self.pc as usize;
+        let size = self.memory.len();
+        (self.memory[pc % size] as u16) << 8 | self.memory[(pc + 1) % size] as u16
+    }
+
+    /// Tells `FX0A` about keys going down and up while it waits.
+    fn set_key(&mut self, key: u8, state: u8) {
+        let key = key as usize;
+        if let Some(wait) = &mut self.key_wait {
+            if state != 0 && self.key[key] == 0 {
+                wait.pressed[key] = true;
+            } else if state == 0 && wait.pressed[key] && wait.released.is_none() {
+                wait.released = Some(key as u8);
+            }
+        }
+        self.key[key] = state;
+    }
+
+    fn set_second_key(&mut self, key: u8, state: u8) {
+        self.second_key[key as usize] = state;
+    }
+
+    /// The sound timer is active. MegaChip sounds are played by `audio` instead.
+    fn is_beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    fn audio(&self) -> &[u8] {
+        self.megachip.as_ref().map_or(&[], |megachip| &megachip.audio)
+    }
+
+    fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `Chip8` running `program` from `0x200`.
+    fn chip8(program: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.initialize(0x200);
+        chip8.load_program(program.to_vec()).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn decayed_pixels_go_dark() {
+        // I := font 0, draw it, erase it, loop
+        let mut chip8 = chip8(&[0xA0, 0x50, 0xD0, 0x15, 0xD0, 0x15, 0x12, 0x06]);
+        chip8.set_display_mode(DisplayMode::Decay);
+        chip8.emulate_frame(2).unwrap();
+        assert_eq!(chip8.frame().pixel(0, 0), 1);
+        chip8.emulate_frame(1).unwrap();
+        assert_eq!(chip8.frame().pixel(0, 0), 1, "still fading");
+        for _ in 0..20 {
+            chip8.emulate_frame(1).unwrap();
+        }
+        assert_eq!(chip8.frame().pixel(0, 0), 0);
+        assert_eq!(chip8.frame().intensity.unwrap()[0], 0);
+    }
+
+    /// Draws the digit 0, 4x5 pixels, at (62, 30) with `quirks`; one more cycle draws it again.
+    fn draw_at_the_corner(quirks: Quirks) -> Chip8 {
+        let mut chip8 = chip8(&[0x60, 0x3E, 0x61, 0x1E, 0xA0, 0x50, 0xD0, 0x15, 0xD0, 0x15]);
+        chip8.set_quirks(quirks);
+        for _ in 0..4 {
+            chip8.emulate_cycle().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn sprites_are_clipped_at_the_edges() {
+        let mut chip8 = draw_at_the_corner(Quirks::default());
+        let frame = chip8.frame();
+        assert_eq!((frame.pixel(62, 30), frame.pixel(63, 31)), (1, 0));
+        assert_eq!(frame.pixel(0, 30), 0, "nothing past the right edge");
+        assert_eq!((frame.pixel(62, 0), frame.pixel(1, 0)), (0, 0), "nothing past the bottom");
+        assert_eq!(chip8.v[0xF], 0);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.v[0xF], 1);
+    }
+
+    #[test]
+    fn sprites_wrap_around_the_edges() {
+        let mut chip8 = draw_at_the_corner(Quirks {
+            clip_sprites: false,
+            ..Quirks::default()
+        });
+        let frame = chip8.frame();
+        assert_eq!(frame.pixel(62, 30), 1);
+        assert_eq!((frame.pixel(0, 30), frame.pixel(1, 30)), (1, 1), "the right half on the left");
+        // the third row, 0x90, on the top row
+        assert_eq!((frame.pixel(62, 0), frame.pixel(63, 0), frame.pixel(1, 0)), (1, 0, 1));
+        assert_eq!(chip8.v[0xF], 0);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.v[0xF], 1);
+        assert_eq!(chip8.frame().pixel(1, 0), 0);
+    }
+
+    #[test]
+    fn super_chip_counts_the_clipped_and_colliding_rows() {
+        let mut chip8 = draw_at_the_corner(Quirks::preset("schip").unwrap());
+        assert_eq!(chip8.v[0xF], 3, "3 rows past the bottom");
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.v[0xF], 5, "and 2 rows colliding");
+        assert_eq!(chip8.frame().pixel(62, 30), 0);
+    }
+
+    #[test]
+    fn xo_chip_planes_are_drawn_and_cleared_apart() {
+        // I := font 0, plane 3, draw 0 on the first plane and 1 on the second, plane 2, clear, draw again
+        let mut chip8 = chip8(&[0xA0, 0x50, 0xF3, 0x01, 0xD0, 0x05, 0xF2, 0x01, 0x00, 0xE0, 0xD0, 0x05]);
+        assert_eq!(chip8.frame().planes, 1);
+        for _ in 0..3 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(chip8.frame().planes, 2);
+        assert_eq!(chip8.frame().pixel(0, 0), 1, "0 only");
+        assert_eq!(chip8.frame().pixel(2, 0), 3, "both");
+        assert_eq!(chip8.frame().pixel(2, 1), 2, "1 only");
+        assert_eq!(chip8.v[0xF], 0);
+
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.frame().pixel(2, 0), 1, "the first plane is kept");
+        assert_eq!(chip8.frame().pixel(2, 1), 0);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.frame().pixel(0, 0), 3, "0 on the second plane");
+        assert_eq!(chip8.v[0xF], 0, "nothing was lit on the second plane");
+    }
+
+    /// A `Chip8` running the MegaChip `program` from `0x200`.
+    fn megachip(program: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(Platform::MegaChip);
+        chip8.initialize(Platform::MegaChip.start());
+        chip8.load_program(program.to_vec()).unwrap();
+        chip8
+    }
+
+    fn screen(chip8: &Chip8, x: usize, y: usize) -> Color {
+        chip8.frame().true_color.expect("Not in MegaChip mode!")[y * MEGACHIP_WIDTH + x]
+    }
+
+    #[test]
+    fn megachip_mode_resizes_the_display() {
+        let mut chip8 = megachip(&[0x00, 0x11, 0x00, 0x10]);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!((chip8.frame().width, chip8.frame().height), (256, 192));
+        chip8.emulate_cycle().unwrap();
+        assert_eq!((chip8.frame().width, chip8.frame().height), (64, 32));
+        assert!(chip8.frame().true_color.is_none());
+    }
+
+    #[test]
+    fn megachip_sprites_use_the_palette_and_collide_with_their_colour() {
+        let mut program = vec![
+            0x00, 0x11, // MegaChip mode
+            0x01, 0x00, 0x02, 0x40, // I := 0x000240
+            0x02, 0x02, // 2 colours
+            0x03, 0x02, 0x04, 0x02, // 2x2 sprites
+            0x01, 0x00, 0x02, 0x50, // I := 0x000250
+            0x60, 0x10, 0x61, 0x08, // v0 := 16, v1 := 8
+            0xD0, 0x10, 0xD0, 0x10, // draw twice
+            0x09, 0x01, 0xD0, 0x10, // collide with colour 1, draw
+            0x00, 0xE0, 0x12, 0x1E, // show, loop
+        ];
+        program.resize(0x40, 0);
+        program.extend([0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF].iter());
+        program.resize(0x50, 0);
+        program.extend([0x01, 0x02, 0x00, 0x01].iter());
+        let mut chip8 = megachip(&program);
+
+        for _ in 0..9 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(chip8.i, 0x250);
+        assert_eq!(chip8.v[0xF], 0, "drawn on a blank screen");
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.v[0xF], 0, "no collision colour yet");
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.v[0xF], 1);
+
+        assert_eq!(screen(&chip8, 16, 8), (0, 0, 0), "shown by 00E0");
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(screen(&chip8, 16, 8), (0xFF, 0x00, 0x00));
+        assert_eq!(screen(&chip8, 17, 8), (0x00, 0x00, 0xFF));
+        assert_eq!(screen(&chip8, 16, 9), (0x00, 0x00, 0x00), "transparent");
+        assert_eq!(screen(&chip8, 17, 9), (0xFF, 0x00, 0x00));
+    }
+
+    #[test]
+    fn megachip_draws_the_font_in_white() {
+        // MegaChip mode, I := font 0, draw it, show
+        let mut chip8 = megachip(&[0x00, 0x11, 0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x00, 0xE0]);
+        for _ in 0..5 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(screen(&chip8, 0, 0), (0xFF, 0xFF, 0xFF));
+        assert_eq!(screen(&chip8, 4, 0), (0x00, 0x00, 0x00));
+    }
+
+    #[test]
+    fn megachip_samples_play_until_stopped() {
+        let mut program = vec![
+            0x00, 0x11, // MegaChip mode
+            0x01, 0x00, 0x02, 0x20, // I := 0x000220
+            0x06, 0x01, // play once
+            0x12, 0x08, // loop
+        ];
+        program.resize(0x20, 0);
+        // 8000Hz, 4 samples
+        program.extend([0x1F, 0x40, 0x00, 0x00, 0x04, 0x00, 0x10, 0x20, 0x30, 0x40].iter());
+        let mut chip8 = megachip(&program);
+        chip8.emulate_frame(3).unwrap();
+        let audio = chip8.audio();
+        assert_eq!(audio.len(), 23, "4 samples at 8000Hz");
+        assert_eq!((audio[0], audio[22]), (0x10, 0x40));
+        chip8.emulate_frame(1).unwrap();
+        assert!(chip8.audio().is_empty());
+
+        // loop it, then stop it
+        chip8.write(0x207, 0x00);
+        chip8.write(0x208, 0x07);
+        chip8.write(0x209, 0x00);
+        chip8.pc = 0x206;
+        chip8.emulate_frame(1).unwrap();
+        assert_eq!(chip8.audio().len(), (AUDIO_RATE / 60) as usize);
+        chip8.emulate_frame(1).unwrap();
+        assert!(chip8.audio().is_empty());
+    }
+
+    #[test]
+    fn arithmetic_wraps_around() {
+        // v0 := 0xFF + 2, v1 := 1 - 2, v3 := 3 - 1, v2 := 2 - 3
+        let mut chip8 = chip8(&[
+            0x60, 0xFF, 0x70, 0x02, 0x61, 0x01, 0x62, 0x02, 0x81, 0x25, 0x62, 0x03, 0x63, 0x01, 0x83, 0x27, 0x82, 0x37,
+        ]);
+        chip8.emulate_frame(8).unwrap();
+        assert_eq!(chip8.v[0x0], 0x01);
+        assert_eq!(chip8.v[0x1], 0xFF);
+        assert_eq!(chip8.v[0x3], 0x02);
+        assert_eq!(chip8.v[0xF], 1);
+        chip8.emulate_frame(1).unwrap();
+        assert_eq!(chip8.v[0x2], 0xFF);
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn shifts_set_vf_to_the_bit_shifted_out() {
+        // v0 := 0x81 << 1, v1 := 0x10 << 1, then v3 := v2 >> 1 with the VIP's quirk
+        let mut chip8 = chip8(&[0x60, 0x81, 0x80, 0x0E, 0x61, 0x10, 0x81, 0x0E, 0x62, 0x81, 0x83, 0x26]);
+        chip8.emulate_frame(2).unwrap();
+        assert_eq!((chip8.v[0x0], chip8.v[0xF]), (0x02, 1));
+        chip8.emulate_frame(2).unwrap();
+        assert_eq!((chip8.v[0x1], chip8.v[0xF]), (0x20, 0));
+
+        chip8.set_quirks(Quirks::preset("vip").unwrap());
+        chip8.emulate_frame(2).unwrap();
+        assert_eq!((chip8.v[0x3], chip8.v[0xF]), (0x40, 1));
+    }
+
+    #[test]
+    fn vip_timing_masks_the_key_of_skips() {
+        let mut chip8 = chip8(&[]);
+        chip8.v[0x0] = 0x15;
+        chip8.set_key(0x5, 1);
+        assert_eq!(chip8.vip_cycles(0xE09E), VIP_FETCH_CYCLES + 14 + 4, "skips like key 5");
+        assert_eq!(chip8.vip_cycles(0xE0A1), VIP_FETCH_CYCLES + 10 + 4);
+    }
+
+    #[test]
+    fn chip8x_reads_the_second_keypad_apart() {
+        // skip the next instruction if key 3 of the second keypad is pressed
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(Platform::Chip8x);
+        chip8.initialize(Platform::Chip8x.start());
+        chip8.load_program(vec![0x60, 0x03, 0xE0, 0xF2, 0x00, 0xE0, 0x00, 0xE0]).unwrap();
+        chip8.set_key(0x3, 1);
+        chip8.emulate_frame(2).unwrap();
+        assert_eq!(chip8.pc, 0x304, "only the first keypad is pressed");
+
+        chip8.set_second_key(0x3, 1);
+        chip8.pc = 0x302;
+        chip8.emulate_frame(1).unwrap();
+        assert_eq!(chip8.pc, 0x306);
+    }
+
+    #[test]
+    fn programs_too_big_for_memory_are_refused() {
+        let mut chip8 = chip8(&[]);
+        assert!(chip8.load_program(vec![0; 0x1000 - 0x200]).is_ok());
+        assert_eq!(
+            chip8.load_program(vec![0; 0x1000 - 0x1FF]),
+            Err("the program is 3585 bytes, only 3584 fit in memory from 0x200".to_string())
+        );
+    }
+
+    #[test]
+    fn programs_starting_past_the_memory_are_refused() {
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(Platform::Dream6800);
+        chip8.initialize(0x900);
+        assert_eq!(chip8.load_program(vec![0; 2]), Err("the start 0x900 is past the end of memory".to_string()));
+    }
+
+    #[test]
+    fn native_runs_only_the_configured_routines() {
+        // draw font 0, call 0x2A0 then 0x2B0
+        let mut chip8 = chip8(&[0xA0, 0x50, 0xD0, 0x15, 0x02, 0xA0, 0x02, 0xB0]);
+        chip8.set_machine_code(MachineCode::Native, [(0x2A0, routine("clear").unwrap())].iter().copied().collect());
+        chip8.emulate_frame(3).unwrap();
+        assert!(chip8.display.iter().all(|pixel| *pixel == 0));
+        match chip8.emulate_frame(1) {
+            Err(Halt::Error(error)) => assert_eq!(error, Error::MachineCode { routine: 0x2B0, address: 0x206 }),
+            _ => panic!("expected a machine code error"),
+        }
+    }
+
+    /// A `Chip8` waiting for a key in V3, then looping, with or without the release.
+    fn key_wait(key_release: bool) -> Chip8 {
+        let mut chip8 = chip8(&[0xF3, 0x0A, 0x12, 0x02]);
+        chip8.set_quirks(Quirks {
+            key_release,
+            ..Quirks::default()
+        });
+        chip8
+    }
+
+    /// Runs `FX0A` once more, returning whether it's done.
+    fn key_waited(chip8: &mut Chip8) -> bool {
+        chip8.emulate_cycle().unwrap();
+        chip8.pc == 0x202
+    }
+
+    #[test]
+    fn key_waits_end_when_the_key_is_let_go() {
+        let mut chip8 = key_wait(true);
+        assert!(!key_waited(&mut chip8));
+        assert_eq!(chip8.registers().waiting_for_key, Some(3));
+        chip8.set_key(0xF, 1);
+        assert!(!key_waited(&mut chip8), "still down");
+        chip8.set_key(0xF, 0);
+        assert!(key_waited(&mut chip8));
+        assert_eq!(chip8.v[3], 0xF);
+        assert_eq!(chip8.registers().waiting_for_key, None);
+    }
+
+    #[test]
+    fn keys_held_before_the_wait_are_ignored() {
+        let mut chip8 = key_wait(true);
+        chip8.set_key(0x5, 1);
+        assert!(!key_waited(&mut chip8));
+        chip8.set_key(0x5, 0);
+        assert!(!key_waited(&mut chip8), "pressed before the wait");
+        chip8.set_key(0x5, 1);
+        chip8.set_key(0x5, 0);
+        assert!(key_waited(&mut chip8));
+        assert_eq!(chip8.v[3], 0x5);
+    }
+
+    #[test]
+    fn dream_6800_key_waits_end_on_the_press() {
+        let mut chip8 = key_wait(false);
+        assert!(!key_waited(&mut chip8));
+        assert_eq!(chip8.registers().waiting_for_key, Some(3));
+        chip8.set_key(0x7, 1);
+        assert!(key_waited(&mut chip8));
+        assert_eq!(chip8.v[3], 0x7);
+        assert_eq!(Platform::Dream6800.quirks().map(|quirks| quirks.key_release), Some(false));
     }
 }
@@ -0,0 +1,93 @@
+/// Turns an opcode into its assembly mnemonic, using the same notation as the
+/// comments in `Chip8::execute_opcode` (Cowgod's Chip-8 Technical Reference).
+/// Anything that isn't an instruction is written as a `DW` data word, so the
+/// output can be fed back into the assembler.
+pub fn disassemble(opcode: u16) -> String {
+    let nnn = opcode & 0x0FFF;
+    let n = opcode & 0x000F;
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let kk = opcode & 0x00FF;
+
+    match opcode {
+        0x00E0 => "CLS".to_string(),
+        0x00EE => "RET".to_string(),
+        0x0000..=0x0FFF => format!("SYS 0x{:03X}", nnn),
+        0x1000..=0x1FFF => format!("JP 0x{:03X}", nnn),
+        0x2000..=0x2FFF => format!("CALL 0x{:03X}", nnn),
+        0x3000..=0x3FFF => format!("SE V{:X}, 0x{:02X}", x, kk),
+        0x4000..=0x4FFF => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        0x5000..=0x5FFF if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000..=0x6FFF => format!("LD V{:X}, 0x{:02X}", x, kk),
+        0x7000..=0x7FFF => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        0x8000..=0x8FFF => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => format!("DW 0x{:04X}", opcode),
+        },
+        0x9000..=0x9FFF if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000..=0xAFFF => format!("LD I, 0x{:03X}", nnn),
+        0xB000..=0xBFFF => format!("JP V0, 0x{:03X}", nnn),
+        0xC000..=0xCFFF => format!("RND V{:X}, 0x{:02X}", x, kk),
+        0xD000..=0xDFFF => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000..=0xEFFF => match kk {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => format!("DW 0x{:04X}", opcode),
+        },
+        0xF000..=0xFFFF => match kk {
+            0x01 if x <= 0x3 => format!("PLANE {}", x),
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => format!("DW 0x{:04X}", opcode),
+        },
+        _ => format!("DW 0x{:04X}", opcode),
+    }
+}
+
+/// Disassembles a whole program, one line per 2-byte word.
+/// A listing prefixes every line with its address and raw word:
+///```
+/// 0x200: 00E0  CLS
+/// 0x202: A22A  LD I, 0x22A
+///```
+/// Without it, the output is plain source for the assembler.
+/// A trailing odd byte is written as `DB`.
+pub fn disassemble_program(program: &[u8], start: u16, listing: bool) -> Vec<String> {
+    program
+        .chunks(2)
+        .enumerate()
+        .map(|(n, word)| {
+            let address = start as usize + n * 2;
+            let (raw, text) = match *word {
+                [high, low] => {
+                    let opcode = (high as u16) << 8 | low as u16;
+                    (format!("{:04X}", opcode), disassemble(opcode))
+                }
+                [byte] => (format!("{:02X}  ", byte), format!("DB 0x{:02X}", byte)),
+                _ => unreachable!(),
+            };
+
+            if listing {
+                format!("0x{:03X}: {}  {}", address, raw, text)
+            } else {
+                text
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+/// How far a stick has to be pushed before it counts as a direction.
+const STICK_DEAD_ZONE: f32 = 0.5;
+
+/// A physical control on a controller that can be bound to a CHIP-8 key.
+/// Sticks are treated as four digital directions each.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Control {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    RightTrigger,
+    LeftTrigger2,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftStickUp,
+    LeftStickDown,
+    LeftStickLeft,
+    LeftStickRight,
+    RightStickUp,
+    RightStickDown,
+    RightStickLeft,
+    RightStickRight,
+}
+
+impl Control {
+    const ALL: [Control; 22] = [
+        Control::DPadUp,
+        Control::DPadDown,
+        Control::DPadLeft,
+        Control::DPadRight,
+        Control::South,
+        Control::East,
+        Control::North,
+        Control::West,
+        Control::LeftTrigger,
+        Control::RightTrigger,
+        Control::LeftTrigger2,
+        Control::RightTrigger2,
+        Control::Select,
+        Control::Start,
+        Control::LeftStickUp,
+        Control::LeftStickDown,
+        Control::LeftStickLeft,
+        Control::LeftStickRight,
+        Control::RightStickUp,
+        Control::RightStickDown,
+        Control::RightStickLeft,
+        Control::RightStickRight,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Control> {
+        Control::ALL
+            .iter()
+            .copied()
+            .find(|control| format!("{:?}", control).eq_ignore_ascii_case(name))
+    }
+
+    fn from_button(button: Button) -> Option<Control> {
+        match button {
+            Button::DPadUp => Some(Control::DPadUp),
+            Button::DPadDown => Some(Control::DPadDown),
+            Button::DPadLeft => Some(Control::DPadLeft),
+            Button::DPadRight => Some(Control::DPadRight),
+            Button::South => Some(Control::South),
+            Button::East => Some(Control::East),
+            Button::North => Some(Control::North),
+            Button::West => Some(Control::West),
+            Button::LeftTrigger => Some(Control::LeftTrigger),
+            Button::RightTrigger => Some(Control::RightTrigger),
+            Button::LeftTrigger2 => Some(Control::LeftTrigger2),
+            Button::RightTrigger2 => Some(Control::RightTrigger2),
+            Button::Select => Some(Control::Select),
+            Button::Start => Some(Control::Start),
+            _ => None,
+        }
+    }
+}
+
+/// Stick axes, split from `gilrs` so the mapping can be fed synthetic events.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stick {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+impl Stick {
+    fn from_axis(axis: Axis) -> Option<Stick> {
+        match axis {
+            Axis::LeftStickX => Some(Stick::LeftX),
+            Axis::LeftStickY => Some(Stick::LeftY),
+            Axis::RightStickX => Some(Stick::RightX),
+            Axis::RightStickY => Some(Stick::RightY),
+            _ => None,
+        }
+    }
+
+    /// Directions reached by pushing the stick towards negative and positive values.
+    /// Y axes point up, like `gilrs` reports them.
+    fn directions(self) -> (Control, Control) {
+        match self {
+            Stick::LeftX => (Control::LeftStickLeft, Control::LeftStickRight),
+            Stick::LeftY => (Control::LeftStickDown, Control::LeftStickUp),
+            Stick::RightX => (Control::RightStickLeft, Control::RightStickRight),
+            Stick::RightY => (Control::RightStickDown, Control::RightStickUp),
+        }
+    }
+}
+
+/// A controller event, independent of the backend that produced it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ControllerEvent {
+    Connected(usize),
+    Disconnected(usize),
+    Pressed(usize, Control),
+    Released(usize, Control),
+    Moved(usize, Stick, f32),
+}
+
+/// Binds controls to keys of the hex keypad (`0x0`-`0xF`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    bindings: HashMap<Control, u8>,
+}
+
+impl Default for Mapping {
+    ///```
+    ///Keypad          Controller
+    ///+-+-+-+-+
+    ///|1|2|3|C|       Up    = 2 / Left  = 4 / Right = 6 / Down  = 8
+    ///+-+-+-+-+       South = 5 / East  = 0 / West  = A / North = B
+    ///|4|5|6|D|       LT/LT2 = 1 / RT/RT2 = C / Select = E / Start = F
+    ///+-+-+-+-+
+    ///|7|8|9|E|       Both sticks mirror the D-pad.
+    ///+-+-+-+-+
+    ///|A|0|B|F|
+    ///+-+-+-+-+
+    ///```
+    fn default() -> Self {
+        let bindings = [
+            (Control::DPadUp, 0x2),
+            (Control::DPadDown, 0x8),
+            (Control::DPadLeft, 0x4),
+            (Control::DPadRight, 0x6),
+            (Control::LeftStickUp, 0x2),
+            (Control::LeftStickDown, 0x8),
+            (Control::LeftStickLeft, 0x4),
+            (Control::LeftStickRight, 0x6),
+            (Control::RightStickUp, 0x2),
+            (Control::RightStickDown, 0x8),
+            (Control::RightStickLeft, 0x4),
+            (Control::RightStickRight, 0x6),
+            (Control::South, 0x5),
+            (Control::East, 0x0),
+            (Control::West, 0xA),
+            (Control::North, 0xB),
+            (Control::LeftTrigger, 0x1),
+            (Control::RightTrigger, 0xC),
+            (Control::LeftTrigger2, 0x1),
+            (Control::RightTrigger2, 0xC),
+            (Control::Select, 0xE),
+            (Control::Start, 0xF),
+        ];
+
+        Self {
+            bindings: bindings.iter().copied().collect(),
+        }
+    }
+}
+
+impl Mapping {
+    /// Applies a profile made of `Control = key` lines, e.g. `South = 5`.
+    /// Bindings override the current ones, a key of `none` unbinds the control
+    /// and `#` starts a comment.
+    pub fn apply(&mut self, profile: &str) -> Result<(), String> {
+        for (n, line) in profile.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let key = parts
+                .next()
+                .ok_or_else(|| format!("line {}: expected `Control = key`", n + 1))?
+                .trim();
+
+            self.bind(name, key)
+                .map_err(|error| format!("line {}: {}", n + 1, error))?;
+        }
+
+        Ok(())
+    }
+
+    /// Binds a control, by name, to a hex key or to `none`.
+    pub fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+        let control =
+            Control::from_name(name).ok_or_else(|| format!("unknown control `{}`", name))?;
+
+        if key.eq_ignore_ascii_case("none") {
+            self.bindings.remove(&control);
+        } else {
+            self.bindings.insert(control, parse_key(key)?);
+        }
+        Ok(())
+    }
+
+    /// Applies the profile stored next to the ROM (`pong.ch8` uses `pong.pad`), if any.
+    pub fn apply_rom_profile(&mut self, rom: &Path) {
+        let profile = rom.with_extension("pad");
+        if let Ok(content) = fs::read_to_string(&profile) {
+            let mut mapping = self.clone();
+            match mapping.apply(&content) {
+                Ok(()) => *self = mapping,
+                Err(error) => eprintln!("Ignoring gamepad profile {:?}: {}", profile, error),
+            }
+        }
+    }
+
+    pub fn set(&mut self, control: Control, key: u8) {
+        self.bindings.insert(control, key);
+    }
+
+    pub fn key(&self, control: Control) -> Option<u8> {
+        self.bindings.get(&control).copied()
+    }
+}
+
+/// Parses a keypad key written in hex (`0`-`F`, optionally prefixed by `0x`).
+pub fn parse_key(key: &str) -> Result<u8, String> {
+    u8::from_str_radix(key.trim_start_matches("0x"), 16)
+        .ok()
+        .filter(|key| *key <= 0xF)
+        .ok_or_else(|| format!("invalid key `{}`", key))
+}
+
+/// Turns controller events into keypad state changes.
+/// Several controls (or controllers) may share a key, so a key is only
+/// released once nothing holding it is pressed anymore.
+pub struct Mapper {
+    mapping: Mapping,
+    held: HashMap<(usize, Control), u8>,
+    presses: [u8; 16],
+}
+
+impl Mapper {
+    pub fn new(mapping: Mapping) -> Self {
+        Self {
+            mapping,
+            held: HashMap::new(),
+            presses: [0; 16],
+        }
+    }
+
+    /// Returns the `(key, state)` pairs to forward to `Chip8::set_key`.
+    pub fn handle(&mut self, event: ControllerEvent) -> Vec<(u8, u8)> {
+        let mut changes = Vec::new();
+
+        match event {
+            ControllerEvent::Connected(id) => eprintln!("Gamepad {} connected", id),
+            ControllerEvent::Disconnected(id) => {
+                eprintln!("Gamepad {} disconnected", id);
+                let controls: Vec<Control> = self
+                    .held
+                    .keys()
+                    .filter(|(pad, _)| *pad == id)
+                    .map(|(_, control)| *control)
+                    .collect();
+                for control in controls {
+                    self.release(id, control, &mut changes);
+                }
+            }
+            ControllerEvent::Pressed(id, control) => self.press(id, control, &mut changes),
+            ControllerEvent::Released(id, control) => self.release(id, control, &mut changes),
+            ControllerEvent::Moved(id, stick, value) => {
+                let (negative, positive) = stick.directions();
+                if value <= -STICK_DEAD_ZONE {
+                    self.release(id, positive, &mut changes);
+                    self.press(id, negative, &mut changes);
+                } else if value >= STICK_DEAD_ZONE {
+                    self.release(id, negative, &mut changes);
+                    self.press(id, positive, &mut changes);
+                } else {
+                    self.release(id, negative, &mut changes);
+                    self.release(id, positive, &mut changes);
+                }
+            }
+        }
+
+        changes
+    }
+
+    fn press(&mut self, id: usize, control: Control, changes: &mut Vec<(u8, u8)>) {
+        if self.held.contains_key(&(id, control)) {
+            return;
+        }
+
+        if let Some(key) = self.mapping.key(control) {
+            self.held.insert((id, control), key);
+            self.presses[key as usize] += 1;
+            if self.presses[key as usize] == 1 {
+                changes.push((key, 1));
+            }
+        }
+    }
+
+    fn release(&mut self, id: usize, control: Control, changes: &mut Vec<(u8, u8)>) {
+        if let Some(key) = self.held.remove(&(id, control)) {
+            self.presses[key as usize] -= 1;
+            if self.presses[key as usize] == 0 {
+                changes.push((key, 0));
+            }
+        }
+    }
+}
+
+/// Connected controllers, polled once per frame from the main loop.
+/// Controllers may be plugged in and out while the emulator runs.
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+    mapper: Mapper,
+}
+
+impl Gamepads {
+    pub fn new(mapping: Mapping) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(error) => {
+                eprintln!("Gamepad support disabled: {}", error);
+                None
+            }
+        };
+
+        Self {
+            gilrs,
+            mapper: Mapper::new(mapping),
+        }
+    }
+
+    pub fn poll(&mut self) -> Vec<(u8, u8)> {
+        let mut changes = Vec::new();
+        let gilrs = match self.gilrs.as_mut() {
+            Some(gilrs) => gilrs,
+            None => return changes,
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            let id = usize::from(event.id);
+            let event = match event.event {
+                EventType::Connected => Some(ControllerEvent::Connected(id)),
+                EventType::Disconnected => Some(ControllerEvent::Disconnected(id)),
+                EventType::ButtonPressed(button, _) => {
+                    Control::from_button(button).map(|control| ControllerEvent::Pressed(id, control))
+                }
+                EventType::ButtonReleased(button, _) => Control::from_button(button)
+                    .map(|control| ControllerEvent::Released(id, control)),
+                EventType::AxisChanged(axis, value, _) => {
+                    Stick::from_axis(axis).map(|stick| ControllerEvent::Moved(id, stick, value))
+                }
+                _ => None,
+            };
+
+            if let Some(event) = event {
+                changes.extend(self.mapper.handle(event));
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(mapper: &mut Mapper, events: &[ControllerEvent]) -> Vec<(u8, u8)> {
+        events.iter().flat_map(|event| mapper.handle(*event)).collect()
+    }
+
+    #[test]
+    fn buttons_press_and_release_their_key() {
+        let mut mapper = Mapper::new(Mapping::default());
+        let changes = handle(
+            &mut mapper,
+            &[ControllerEvent::Pressed(0, Control::South), ControllerEvent::Released(0, Control::South)],
+        );
+        assert_eq!(changes, [(0x5, 1), (0x5, 0)]);
+    }
+
+    #[test]
+    fn shared_keys_stay_down_until_every_control_is_released() {
+        let mut mapper = Mapper::new(Mapping::default());
+        let changes = handle(
+            &mut mapper,
+            &[
+                ControllerEvent::Pressed(0, Control::LeftTrigger),
+                ControllerEvent::Pressed(0, Control::LeftTrigger2),
+                ControllerEvent::Pressed(1, Control::LeftTrigger),
+                ControllerEvent::Released(0, Control::LeftTrigger),
+                ControllerEvent::Released(1, Control::LeftTrigger),
+            ],
+        );
+        assert_eq!(changes, [(0x1, 1)]);
+        assert_eq!(mapper.handle(ControllerEvent::Released(0, Control::LeftTrigger2)), [(0x1, 0)]);
+    }
+
+    #[test]
+    fn sticks_press_one_direction_at_a_time() {
+        let mut mapper = Mapper::new(Mapping::default());
+        let changes = handle(
+            &mut mapper,
+            &[
+                ControllerEvent::Moved(0, Stick::LeftX, -0.9),
+                ControllerEvent::Moved(0, Stick::LeftX, -0.7),
+                ControllerEvent::Moved(0, Stick::LeftX, 0.8),
+                ControllerEvent::Moved(0, Stick::LeftX, 0.1),
+                ControllerEvent::Moved(0, Stick::LeftY, 0.6),
+            ],
+        );
+        assert_eq!(changes, [(0x4, 1), (0x4, 0), (0x6, 1), (0x6, 0), (0x2, 1)]);
+    }
+
+    #[test]
+    fn disconnecting_releases_held_keys() {
+        let mut mapper = Mapper::new(Mapping::default());
+        let changes = handle(
+            &mut mapper,
+            &[
+                ControllerEvent::Connected(3),
+                ControllerEvent::Pressed(3, Control::Start),
+                ControllerEvent::Disconnected(3),
+            ],
+        );
+        assert_eq!(changes, [(0xF, 1), (0xF, 0)]);
+    }
+
+    #[test]
+    fn profiles_rebind_and_unbind_controls() {
+        let mut mapping = Mapping::default();
+        mapping.apply("# comment\nleftstickup = 0x1\nSouth = none\n").unwrap();
+        assert_eq!(mapping.key(Control::LeftStickUp), Some(0x1));
+        assert_eq!(mapping.key(Control::South), None);
+        assert!(mapping.apply("North = 10").is_err());
+        assert!(mapping.apply("Trigger = 1").is_err());
+
+        let mut mapper = Mapper::new(mapping);
+        assert_eq!(mapper.handle(ControllerEvent::Pressed(0, Control::South)), []);
+        assert_eq!(mapper.handle(ControllerEvent::Moved(0, Stick::LeftY, 1.0)), [(0x1, 1)]);
+    }
+}
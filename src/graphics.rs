@@ -1,6 +1,21 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
 use glium::glutin;
+use glium::texture::RawImage2d;
+use glium::uniforms::{
+    MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction, UniformValue,
+    Uniforms,
+};
 use glium::Surface;
 
+use crate::config::WindowConfig;
+use crate::postfx::{self, Filter, Pass};
+use crate::video::{Frame, SoftwareSink, VideoSink};
+
+const TITLE: &str = "CHIP-8 Emulator";
+
 #[derive(Copy, Clone)]
 pub struct Vertex {
     position: [f32; 2],
@@ -15,6 +30,17 @@ implement_vertex!(Vertex, position, tex_coords);
 
 pub type Color = (u8, u8, u8);
 
+/// Parses a `RRGGBB` hex colour, with or without a leading `#`.
+pub fn parse_color(value: &str) -> Result<Color, String> {
+    let hex = value.trim_start_matches('#');
+    let color = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid colour `{}`", value))?;
+    if hex.len() != 6 {
+        return Err(format!("invalid colour `{}`, expected RRGGBB", value));
+    }
+
+    Ok(((color >> 16) as u8, (color >> 8) as u8, color as u8))
+}
+
 const FRAMEBUFFER_VERTICES: [Vertex; 4] = [
     Vertex {
         position:   [-1.0,  1.0 ],
@@ -35,25 +61,238 @@ const FRAMEBUFFER_VERTICES: [Vertex; 4] = [
 ];
 const FRAMEBUFFER_INDICES: [u32; 4] = [1, 2, 0, 3];
 
+/// Post-processing passes draw into textures, keeping the top row first,
+/// so unlike the window their quad isn't flipped.
+const PASS_VERTICES: [Vertex; 4] = [
+    Vertex {
+        position:   [-1.0,  1.0 ],
+        tex_coords: [ 0.0,  1.0 ],
+    },
+    Vertex {
+        position:   [-1.0, -1.0 ],
+        tex_coords: [ 0.0,  0.0 ],
+    },
+    Vertex {
+        position:   [ 1.0, -1.0 ],
+        tex_coords: [ 1.0,  0.0 ],
+    },
+    Vertex {
+        position:   [ 1.0,  1.0 ],
+        tex_coords: [ 1.0,  1.0 ],
+    },
+];
+
+const PASS_VERTEX_SHADER: &str = "
+    #version 140
+
+    attribute vec2 position;
+    attribute vec2 tex_coords;
+    varying vec2 v_tex_coords;
+
+    void main() {
+        gl_Position = vec4(position, 0.0, 1.0);
+        v_tex_coords = tex_coords;
+    }
+";
+
 pub type Texture = glium::texture::Texture2d;
+
+/// How the display is fitted into the window.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scaling {
+    /// Fills the window, pixels may not be square.
+    Stretch,
+    /// As big as the window allows with square pixels, the rest filled with the background.
+    Fit,
+    /// Like `Fit`, but every pixel is a whole number of window pixels.
+    Integer,
+}
+
+impl Scaling {
+    pub const NAMES: [&'static str; 3] = ["stretch", "fit", "integer"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stretch" => Some(Self::Stretch),
+            "fit" => Some(Self::Fit),
+            "integer" => Some(Self::Integer),
+            _ => None,
+        }
+    }
+
+    /// Where a `width` x `height` display goes in a window, leaving `border` pixels around it.
+    fn viewport(self, (width, height): (u32, u32), window: (u32, u32), border: u32) -> glium::Rect {
+        let available = (
+            window.0.saturating_sub(2 * border).max(1),
+            window.1.saturating_sub(2 * border).max(1),
+        );
+        let scale = (available.0 as f32 / width as f32).min(available.1 as f32 / height as f32);
+        let size = match self {
+            Scaling::Stretch => available,
+            Scaling::Fit => ((width as f32 * scale) as u32, (height as f32 * scale) as u32),
+            Scaling::Integer => {
+                let scale = (scale.floor() as u32).max(1);
+                (width * scale, height * scale)
+            }
+        };
+
+        glium::Rect {
+            left: window.0.saturating_sub(size.0) / 2,
+            bottom: window.1.saturating_sub(size.1) / 2,
+            width: size.0,
+            height: size.1,
+        }
+    }
+}
+
+/// Averages how long drawing takes, shown in the window title once a second.
+/// Waiting for vsync isn't counted, only the upload and the draw calls.
+struct FrameTimer {
+    total: Duration,
+    frames: u32,
+    since: Instant,
+}
+
+impl FrameTimer {
+    fn new() -> Self {
+        Self {
+            total: Duration::default(),
+            frames: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Adds a frame, returning the average once a second has gone by.
+    fn add(&mut self, duration: Duration) -> Option<Duration> {
+        self.total += duration;
+        self.frames += 1;
+        if self.since.elapsed() < Duration::from_secs(1) {
+            return None;
+        }
+
+        let average = self.total / self.frames;
+        *self = Self::new();
+        Some(average)
+    }
+}
+
+/// One pass of the post-processing chain, with the textures it draws into:
+/// the output of the current frame, and the one of the previous frame.
+struct Stage {
+    pass: Pass,
+    program: glium::Program,
+    target: Texture,
+    history: Texture,
+}
+
+impl Stage {
+    fn new(display: &glium::Display, pass: Pass) -> Result<Self, String> {
+        let program = glium::Program::from_source(display, PASS_VERTEX_SHADER, &pass.source, None)
+            .map_err(|error| format!("{}: {}", pass.shader, error))?;
+
+        Ok(Self {
+            pass,
+            program,
+            target: empty_texture(display, 1, 1),
+            history: empty_texture(display, 1, 1),
+        })
+    }
+
+    /// Recreates the textures when the size of the pass output changes.
+    fn resize(&mut self, display: &glium::Display, width: u32, height: u32) {
+        if self.target.width() != width || self.target.height() != height {
+            self.target = empty_texture(display, width, height);
+            self.history = empty_texture(display, width, height);
+        }
+    }
+}
+
+/// A texture cleared to black, since new textures hold garbage.
+fn empty_texture(display: &glium::Display, width: u32, height: u32) -> Texture {
+    let texture = Texture::empty(display, width, height).expect("Couldn't create empty texture!");
+    texture.as_surface().clear_color(0.0, 0.0, 0.0, 1.0);
+    texture
+}
+
+/// The uniforms every pass gets, see `postfx::Preset`.
+struct PassUniforms<'a> {
+    source: &'a Texture,
+    history: &'a Texture,
+    filter: Filter,
+    output_size: (u32, u32),
+    parameters: &'a BTreeMap<String, f32>,
+}
+
+impl Uniforms for PassUniforms<'_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut visit: F) {
+        let (magnify_filter, minify_filter) = match self.filter {
+            Filter::Nearest => (MagnifySamplerFilter::Nearest, MinifySamplerFilter::Nearest),
+            Filter::Linear => (MagnifySamplerFilter::Linear, MinifySamplerFilter::Linear),
+        };
+        let sampler = SamplerBehavior {
+            wrap_function: (SamplerWrapFunction::Clamp, SamplerWrapFunction::Clamp, SamplerWrapFunction::Clamp),
+            magnify_filter,
+            minify_filter,
+            ..Default::default()
+        };
+
+        visit("source", UniformValue::Texture2d(self.source, Some(sampler)));
+        visit("history", UniformValue::Texture2d(self.history, Some(sampler)));
+        let source_size = [self.source.width() as f32, self.source.height() as f32];
+        visit("source_size", UniformValue::Vec2(source_size));
+        visit("output_size", UniformValue::Vec2([self.output_size.0 as f32, self.output_size.1 as f32]));
+        for (name, value) in self.parameters {
+            visit(name, UniformValue::Float(*value));
+        }
+    }
+}
+
+/// Draws the emulated display as a textured quad filling the window.
+/// The quad and the texture are created once, every frame only uploads the pixels.
 pub struct Renderer {
     display: glium::Display,
-    events_loop: glutin::EventsLoop,
     frame: Option<glium::Frame>,
     program: glium::Program,
+    vertex_buffer: glium::VertexBuffer<Vertex>,
+    pass_vertex_buffer: glium::VertexBuffer<Vertex>,
+    index_buffer: glium::IndexBuffer<u32>,
+    /// Resized when the emulated resolution changes.
+    texture: Texture,
+    /// Converts frames to the RGBA pixels uploaded into `texture`.
+    software: SoftwareSink,
+    timer: FrameTimer,
+    /// Shown in the title with the frame time, see `VideoSink::status`.
+    status: String,
+    frame_time: Duration,
+    /// The post-processing chain, see `postfx`.
+    stages: Vec<Stage>,
+    /// The preset `cycle_shader` switches from.
+    shader: String,
+    scaling: Scaling,
+    border: u32,
+    fullscreen: bool,
 }
 
 impl Renderer {
-    pub fn new() -> Self {
-        let events_loop = glutin::EventsLoop::new();
+    /// Window events are delivered through `events_loop`, polled by the caller.
+    pub fn new(config: &WindowConfig, scaling: Scaling, events_loop: &glutin::EventsLoop) -> Self {
+        let monitor = if config.fullscreen {
+            Some(events_loop.get_primary_monitor())
+        } else {
+            None
+        };
         let wb = glutin::WindowBuilder::new()
-            .with_title("CHIP-8 Emulator")
-            .with_dimensions((1024, 768).into());
+            .with_title(TITLE)
+            .with_dimensions((config.width, config.height).into())
+            .with_fullscreen(monitor);
         let cb = glutin::ContextBuilder::new()
-            .with_multisampling(4)
-            .with_vsync(true);
+            .with_multisampling(config.multisampling)
+            .with_vsync(config.vsync);
         let display =
-            glium::Display::new(wb, cb, &events_loop).expect("Couldn't create glium display!");
+            glium::Display::new(wb, cb, events_loop).expect("Couldn't create glium display!");
+        if let Some((x, y)) = config.position {
+            display.gl_window().window().set_position((x, y).into());
+        }
         let frame = None;
 
         let vertex = "
@@ -79,59 +318,194 @@ impl Renderer {
             }
         ";
 
-        let program = glium::Program::from_source(&display, &vertex, &fragment, None)
+        let program = glium::Program::from_source(&display, vertex, fragment, None)
             .expect("Couldn't create shader program!");
 
+        let vertex_buffer = glium::VertexBuffer::new(&display, &FRAMEBUFFER_VERTICES)
+            .expect("Coudln't create vertex buffer!");
+        let pass_vertex_buffer = glium::VertexBuffer::new(&display, &PASS_VERTICES)
+            .expect("Coudln't create vertex buffer!");
+        let index_buffer = glium::index::IndexBuffer::new(
+            &display,
+            glium::index::PrimitiveType::TriangleStrip,
+            &FRAMEBUFFER_INDICES,
+        )
+        .expect("Coudln't create index buffer!");
+        let texture = Texture::empty(&display, 64, 32).expect("Couldn't create empty texture!");
+
         Self {
             display,
-            events_loop,
             frame,
             program,
+            vertex_buffer,
+            pass_vertex_buffer,
+            index_buffer,
+            texture,
+            software: SoftwareSink::new(),
+            timer: FrameTimer::new(),
+            status: String::new(),
+            frame_time: Duration::default(),
+            stages: Vec::new(),
+            shader: "none".to_string(),
+            scaling,
+            border: config.border,
+            fullscreen: config.fullscreen,
+        }
+    }
+
+    fn update_title(&self) {
+        let title = format!(
+            "{} - {} - {:.2} ms/frame",
+            TITLE,
+            self.status,
+            self.frame_time.as_secs_f64() * 1000.0
+        );
+        self.display.gl_window().window().set_title(&title);
+    }
+
+    /// Switches between the window and borderless fullscreen on the current monitor.
+    pub fn toggle_fullscreen(&mut self) {
+        self.fullscreen = !self.fullscreen;
+        let window = self.display.gl_window();
+        let monitor = if self.fullscreen {
+            Some(window.window().get_current_monitor())
+        } else {
+            None
+        };
+        window.window().set_fullscreen(monitor);
+    }
+
+    /// The size and the position of the window, to open it the same way next time.
+    /// Nothing while fullscreen.
+    pub fn geometry(&self) -> Option<(u32, u32, (i32, i32))> {
+        if self.fullscreen {
+            return None;
+        }
+        let window = self.display.gl_window();
+        let size = window.window().get_inner_size()?;
+        let position = window.window().get_position()?;
+        Some((
+            size.width.round() as u32,
+            size.height.round() as u32,
+            (position.x.round() as i32, position.y.round() as i32),
+        ))
+    }
+
+    /// Replaces the post-processing chain with the preset `name`, see `postfx::load`.
+    pub fn set_shader(&mut self, name: &str) -> Result<(), String> {
+        let preset = postfx::load(name)?;
+        let stages = preset
+            .passes
+            .into_iter()
+            .map(|pass| Stage::new(&self.display, pass))
+            .collect::<Result<_, _>>()?;
+
+        self.stages = stages;
+        self.shader = name.to_string();
+        Ok(())
+    }
+
+    /// Switches to the next preset of `postfx::available`, skipping broken ones.
+    pub fn cycle_shader(&mut self) {
+        let names = postfx::available();
+        let current = names.iter().position(|name| *name == self.shader);
+        let start = current.map_or(0, |current| current + 1);
+
+        for name in names.iter().cycle().skip(start).take(names.len()) {
+            match self.set_shader(name) {
+                Ok(()) => {
+                    println!("Shader: {}", name);
+                    return;
+                }
+                Err(error) => eprintln!("Couldn't load shader {}: {}", name, error),
+            }
         }
     }
 
-    pub fn clear_screen(&mut self) {
+    pub fn clear_screen(&mut self, (r, g, b): Color) {
         self.frame
             .as_mut()
             .expect("No frame to clear color!")
-            .clear_color(0.0, 0.0, 0.0, 1.0);
+            .clear_color(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0);
     }
 
-    pub fn draw_screen(&mut self, content: Vec<Vec<Color>>) {
-        let texture = Texture::new(&self.display, content).expect("Couldn't create empty texture!");
+    /// Uploads the frame into the texture, and draws it.
+    pub fn draw_screen(&mut self, frame: &Frame, palette: &[Color]) {
+        self.software.present(frame, palette);
+        let (width, height) = (self.software.width() as u32, self.software.height() as u32);
+        if self.texture.width() != width || self.texture.height() != height {
+            self.texture =
+                Texture::empty(&self.display, width, height).expect("Couldn't create empty texture!");
+        }
+        let image = RawImage2d {
+            data: Cow::Borrowed(self.software.rgba()),
+            width,
+            height,
+            format: glium::texture::ClientFormat::U8U8U8U8,
+        };
+        let rect = glium::Rect {
+            left: 0,
+            bottom: 0,
+            width,
+            height,
+        };
+        self.texture.write(rect, image);
+
+        let surface = self.frame.as_mut().expect("No frame to draw!");
+        let viewport = self.scaling.viewport((width, height), surface.get_dimensions(), self.border);
 
-        let vertex_buffer = glium::VertexBuffer::new(&self.display, &FRAMEBUFFER_VERTICES)
-            .expect("Coudln't create vertex buffer!");
-        let index_buffer = glium::index::IndexBuffer::new(
-            &self.display,
-            glium::index::PrimitiveType::TriangleStrip,
-            &FRAMEBUFFER_INDICES,
-        )
-        .expect("Coudln't create index buffer!");
+        // every pass reads the output of the previous one, then keeps its own for the next frame
+        let mut source = &self.texture;
+        for stage in self.stages.iter_mut() {
+            let size = match stage.pass.scale {
+                Some(scale) => (source.width() * scale, source.height() * scale),
+                None => (viewport.width, viewport.height),
+            };
+            stage.resize(&self.display, size.0, size.1);
+
+            let uniforms = PassUniforms {
+                source,
+                history: &stage.history,
+                filter: stage.pass.filter,
+                output_size: size,
+                parameters: &stage.pass.parameters,
+            };
+            stage
+                .target
+                .as_surface()
+                .draw(
+                    &self.pass_vertex_buffer,
+                    &self.index_buffer,
+                    &stage.program,
+                    &uniforms,
+                    &Default::default(),
+                )
+                .expect("Couldn't draw shader pass!");
+
+            std::mem::swap(&mut stage.target, &mut stage.history);
+            let stage: &Stage = stage;
+            source = &stage.history;
+        }
 
         let uniforms = uniform! {
-            tex: texture.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            tex: source.sampled().magnify_filter(MagnifySamplerFilter::Nearest),
         };
 
-        self.frame
-            .as_mut()
-            .expect("No frame to draw!")
+        let parameters = glium::DrawParameters {
+            viewport: Some(viewport),
+            ..Default::default()
+        };
+        surface
             .draw(
-                &vertex_buffer,
-                &index_buffer,
+                &self.vertex_buffer,
+                &self.index_buffer,
                 &self.program,
                 &uniforms,
-                &Default::default(),
+                &parameters,
             )
             .expect("Couldn't draw to screen!");
     }
 
-    pub fn poll_events(&mut self) -> Vec<glutin::Event> {
-        let mut events = Vec::new();
-        self.events_loop.poll_events(|event| events.push(event));
-        events
-    }
-
     pub fn new_frame(&mut self) {
         let target = self.display.draw();
         self.frame = Some(target);
@@ -144,3 +518,25 @@ impl Renderer {
         }
     }
 }
+
+impl VideoSink for Renderer {
+    fn present(&mut self, frame: &Frame, palette: &[Color]) {
+        let start = Instant::now();
+        self.new_frame();
+        self.clear_screen(palette[0]);
+        self.draw_screen(frame, palette);
+
+        if let Some(average) = self.timer.add(start.elapsed()) {
+            self.frame_time = average;
+            self.update_title();
+        }
+        self.finish_frame();
+    }
+
+    fn status(&mut self, status: &str) {
+        if self.status != status {
+            self.status = status.to_string();
+            self.update_title();
+        }
+    }
+}
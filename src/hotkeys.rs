@@ -0,0 +1,45 @@
+/// What the front-ends can ask of the emulation, besides pressing keypad keys.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Hotkey {
+    Quit,
+    NextPalette,
+    /// Only the window has shaders.
+    NextShader,
+    ToggleFullscreen,
+    Pause,
+    /// Runs one frame and pauses.
+    FrameAdvance,
+    /// Cycles through full speed, half speed and quarter speed.
+    SlowMotion,
+    /// Runs uncapped while the key is held.
+    Turbo(bool),
+    /// More instructions per frame.
+    SpeedUp,
+    SpeedDown,
+    /// Starts or stops recording.
+    Record,
+    /// Saves the display to a PNG file.
+    Screenshot,
+}
+
+/// Keys taken by the front-ends, by the names `Settings::keypad_key` uses.
+/// They are checked before the keypad, so they can't be remapped to it.
+/// Only turbo cares about releases.
+pub fn hotkey(name: &str, pressed: bool) -> Option<Hotkey> {
+    let hotkey = match name {
+        "Tab" => return Some(Hotkey::Turbo(pressed)),
+        "Escape" => Hotkey::Quit,
+        "F5" => Hotkey::Pause,
+        "F6" => Hotkey::FrameAdvance,
+        "F7" => Hotkey::SlowMotion,
+        "F8" => Hotkey::NextPalette,
+        "F2" => Hotkey::Screenshot,
+        "F9" => Hotkey::NextShader,
+        "F11" => Hotkey::ToggleFullscreen,
+        "F12" => Hotkey::Record,
+        "PageUp" => Hotkey::SpeedUp,
+        "PageDown" => Hotkey::SpeedDown,
+        _ => return None,
+    };
+    Some(hotkey).filter(|_| pressed)
+}
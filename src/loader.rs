@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::{ColorConfig, Hints};
+use crate::cpu::Quirks;
+use crate::octo;
+
+/// Extensions of raw programs, CHIP-8, SUPER-CHIP and XO-CHIP.
+const RAW_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+/// A program ready to be handed to `Chip8::load_program`,
+/// with whatever configuration came bundled with it.
+pub struct Rom {
+    pub program: Vec<u8>,
+    pub hints: Hints,
+}
+
+/// Reads a ROM, telling the format apart by its content rather than its extension:
+/// - Octo cartridges, GIF images carrying the source and the options of a game
+/// - zip archives, from which the first ROM found is loaded
+/// - Octo source (`.o8`), compiled on the fly
+/// - anything else is a raw program
+pub fn load(path: &Path) -> Result<Rom, String> {
+    let buffer = fs::read(path).map_err(|error| format!("{:?}: {}", path, error))?;
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    if buffer.starts_with(b"GIF8") {
+        load_cartridge(&buffer)
+    } else if buffer.starts_with(b"PK\x03\x04") {
+        load_zip(buffer)
+    } else if extension == "o8" {
+        load_source(&buffer)
+    } else {
+        Ok(Rom {
+            program: buffer,
+            hints: Hints::default(),
+        })
+    }
+}
+
+fn load_source(buffer: &[u8]) -> Result<Rom, String> {
+    let source = String::from_utf8_lossy(buffer);
+    Ok(Rom {
+        program: octo::compile(&source)?,
+        hints: Hints::default(),
+    })
+}
+
+fn load_zip(buffer: Vec<u8>) -> Result<Rom, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(buffer)).map_err(|error| error.to_string())?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|error| error.to_string())?;
+        let name = file.name().map_err(|error| error.to_string())?.into_owned();
+        let lower = name.to_ascii_lowercase();
+        let extension = lower.rsplit('.').next().unwrap_or("");
+        let known = RAW_EXTENSIONS.contains(&extension) || extension == "o8" || extension == "gif";
+        if file.is_dir() || !known {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).map_err(|error| error.to_string())?;
+        eprintln!("Loading {} from the archive", name);
+
+        return match extension {
+            "gif" => load_cartridge(&content),
+            "o8" => load_source(&content),
+            _ => Ok(Rom {
+                program: content,
+                hints: Hints::default(),
+            }),
+        };
+    }
+
+    Err("no ROM found in the archive".to_string())
+}
+
+/// The JSON payload of an Octo cartridge.
+#[derive(Deserialize)]
+struct Cartridge {
+    program: String,
+    #[serde(default)]
+    options: HashMap<String, serde_json::Value>,
+}
+
+/// Octo stores the payload in the two low bits of the palette index of every pixel,
+/// frame after frame, most significant bits first: four pixels make one byte.
+/// The payload starts with its length, as a 32-bit big endian number,
+/// followed by the UTF-8 JSON of the cartridge.
+fn load_cartridge(buffer: &[u8]) -> Result<Rom, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(buffer).map_err(|error| error.to_string())?;
+
+    let mut bits = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|error| error.to_string())? {
+        bits.extend(frame.buffer.iter().map(|index| index & 0x3));
+    }
+    let bytes: Vec<u8> = bits
+        .chunks_exact(4)
+        .map(|pixels| pixels.iter().fold(0, |byte, bits| byte << 2 | bits))
+        .collect();
+
+    if bytes.len() < 4 {
+        return Err("not an Octo cartridge".to_string());
+    }
+    let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let payload = bytes
+        .get(4..4 + length)
+        .ok_or_else(|| "not an Octo cartridge, or a truncated one".to_string())?;
+
+    let cartridge: Cartridge =
+        serde_json::from_slice(payload).map_err(|error| format!("invalid cartridge: {}", error))?;
+
+    Ok(Rom {
+        program: octo::compile(&cartridge.program)?,
+        hints: octo_options(&cartridge.options),
+    })
+}
+
+/// Translates the options Octo saves with a program.
+fn octo_options(options: &HashMap<String, serde_json::Value>) -> Hints {
+    let flag = |name: &str| options.get(name).and_then(|value| value.as_bool()).unwrap_or(false);
+    let color = |name: &str| {
+        options
+            .get(name)
+            .and_then(|value| value.as_str())
+            .map(|color| color.trim_start_matches('#').to_string())
+    };
+
+    let quirks = Quirks {
+        shift_vy: !flag("shiftQuirks"),
+        load_store_increment: !flag("loadStoreQuirks"),
+        jump_vx: flag("jumpQuirks"),
+        vf_reset: flag("logicQuirks"),
+        clip_sprites: flag("clipQuirks"),
+        vf_row_count: false,
+        key_release: true,
+    };
+
+    let colors = match (color("fillColor"), color("backgroundColor")) {
+        (Some(foreground), Some(background)) => Some(ColorConfig {
+            palette: None,
+            background: Some(background),
+            foreground: Some(foreground),
+            foreground2: color("fillColor2"),
+            blend: color("blendColor"),
+        }),
+        _ => None,
+    };
+
+    Hints {
+        speed: options
+            .get("tickrate")
+            .and_then(|value| value.as_u64())
+            .map(|tickrate| tickrate as u32),
+        quirks: Some(quirks),
+        colors,
+        keys: Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GIF carrying `payload` like Octo does, after `length`, spread over two frames,
+    /// with the unused high bits of the palette indices set to make sure they are ignored.
+    fn cartridge(payload: &[u8], length: usize) -> Vec<u8> {
+        let mut bytes = (length as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        let mut pixels: Vec<u8> = bytes
+            .iter()
+            .flat_map(|byte| (0..4).rev().map(move |pair| byte >> (pair * 2) & 0x3 | 0x4))
+            .collect();
+        const WIDTH: usize = 16;
+        let rows = pixels.len().div_ceil(WIDTH * 2);
+        pixels.resize(rows * 2 * WIDTH, 0);
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, WIDTH as u16, rows as u16, &[0; 8 * 3]).unwrap();
+            for frame in pixels.chunks(rows * WIDTH) {
+                let frame = gif::Frame {
+                    width: WIDTH as u16,
+                    height: rows as u16,
+                    buffer: frame.to_vec().into(),
+                    ..Default::default()
+                };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        gif
+    }
+
+    #[test]
+    fn cartridges_carry_the_program_and_its_options() {
+        let payload = r##"{
+            "program": ": main v0 := 7",
+            "options": {
+                "tickrate": 30,
+                "shiftQuirks": true,
+                "jumpQuirks": true,
+                "fillColor": "#FF0000",
+                "fillColor2": "#00FF00",
+                "backgroundColor": "#000000"
+            }
+        }"##;
+        let rom = load_cartridge(&cartridge(payload.as_bytes(), payload.len())).unwrap();
+        assert_eq!(rom.program, [0x60, 0x07]);
+        assert_eq!(rom.hints.speed, Some(30));
+
+        let quirks = rom.hints.quirks.unwrap();
+        assert!(!quirks.shift_vy && quirks.load_store_increment && quirks.jump_vx);
+        assert!(!quirks.vf_reset && !quirks.clip_sprites);
+
+        let colors = rom.hints.colors.unwrap();
+        assert_eq!(colors.foreground.as_deref(), Some("FF0000"));
+        assert_eq!(colors.background.as_deref(), Some("000000"));
+        assert_eq!(colors.foreground2.as_deref(), Some("00FF00"));
+        assert_eq!(colors.blend, None);
+    }
+
+    #[test]
+    fn truncated_cartridges_are_refused() {
+        let payload = br#"{ "program": ": main" }"#;
+        assert!(load_cartridge(&cartridge(payload, payload.len())).is_ok());
+        let error = load_cartridge(&cartridge(payload, payload.len() + 100)).err().unwrap();
+        assert!(error.contains("truncated"), "{}", error);
+    }
+}
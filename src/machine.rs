@@ -0,0 +1,50 @@
+use crate::cpu::{Error, Registers};
+use crate::video::Frame;
+
+/// What the front-ends run: the `Chip8` interpreter, or a whole computer
+/// running the original interpreter like the `Vip`.
+pub trait Machine {
+    /// Runs a 60Hz frame, `speed` instructions unless the machine keeps its own pace.
+    /// Stops where the program halts, leaving the rest of the frame undone.
+    fn emulate_frame(&mut self, speed: u32) -> Result<(), Halt>;
+
+    /// What the front-ends should show.
+    fn frame(&self) -> Frame<'_>;
+
+    /// Presses (1) or releases (0) a keypad key.
+    fn set_key(&mut self, key: u8, state: u8);
+
+    /// Presses (1) or releases (0) a key of the second keypad of CHIP-8X.
+    /// Machines with a single keypad ignore it.
+    fn set_second_key(&mut self, _key: u8, _state: u8) {}
+
+    /// The buzzer should be playing.
+    fn is_beeping(&self) -> bool;
+
+    /// The sampled sound played during the last frame, unsigned 8-bit mono at `AUDIO_RATE`,
+    /// shorter when it stopped. Empty for machines that only have a buzzer.
+    fn audio(&self) -> &[u8] {
+        &[]
+    }
+
+    /// The CHIP-8 registers, wherever the machine keeps them.
+    fn registers(&self) -> Registers;
+
+    /// The opcode the next CHIP-8 instruction will execute.
+    fn next_opcode(&self) -> u16;
+
+    /// SHA-1 of the loaded program, used to look the game up in the ROM database and the configuration.
+    fn rom_hash(&self) -> &str;
+}
+
+/// Samples per second of `Machine::audio`.
+pub const AUDIO_RATE: u32 = 44100;
+
+/// Why a frame stopped before its end.
+#[derive(Debug)]
+pub enum Halt {
+    /// Pauses the emulation, like a breakpoint, saying why.
+    Break(String),
+    /// The program can't go on.
+    Error(Error),
+}
@@ -1,120 +1,453 @@
 #[macro_use]
 extern crate glium;
 
-use std::fs::File;
-use std::path::Path;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use glium::glutin::{Event, KeyboardInput, ElementState, VirtualKeyCode};
-use glium::glutin::WindowEvent;
+use clap::Parser;
+use glium::glutin::{Event, KeyboardInput, ElementState};
+use glium::glutin::{self, WindowEvent};
 
 mod graphics;
 mod cpu;
+mod gamepad;
+mod cli;
+mod disasm;
+mod asm;
+mod config;
+mod romdb;
+mod loader;
+mod octo;
+mod video;
+mod tui;
+mod postfx;
+mod palette;
+mod hotkeys;
+mod pace;
+mod record;
+mod machine;
+mod cdp1802;
+mod vip;
+mod font;
 
-///```
-///Keypad                   Keyboard
-///+-+-+-+-+                +-+-+-+-+
-///|1|2|3|C|                |1|2|3|4|
-///+-+-+-+-+                +-+-+-+-+
-///|4|5|6|D|                |Q|W|E|R|
-///+-+-+-+-+       =>       +-+-+-+-+
-///|7|8|9|E|                |A|S|D|F|
-///+-+-+-+-+                +-+-+-+-+
-///|A|0|B|F|                |Z|X|C|V|
-///+-+-+-+-+                +-+-+-+-+
-///```
-fn keyboard_to_keypad(keycode: VirtualKeyCode) -> i8{
-    match keycode{
-        VirtualKeyCode::Key1 => 0x1,
-        VirtualKeyCode::Key2 => 0x2,
-        VirtualKeyCode::Key3 => 0x3,
-        VirtualKeyCode::Key4 => 0xC,
-        VirtualKeyCode::Q => 0x4,
-        VirtualKeyCode::W => 0x5,
-        VirtualKeyCode::E => 0x6,
-        VirtualKeyCode::R => 0xD,
-        VirtualKeyCode::A => 0x7,
-        VirtualKeyCode::S => 0x8,
-        VirtualKeyCode::D => 0x9,
-        VirtualKeyCode::F => 0xE,
-        VirtualKeyCode::Z => 0xA,
-        VirtualKeyCode::X => 0x0,
-        VirtualKeyCode::C => 0xB,
-        VirtualKeyCode::V => 0xF,
-        _ => -1,
-    }
+use cli::{Cli, Command, MachineType, RunOptions, Video};
+use config::{Config, Settings};
+use romdb::{Database, RomInfo};
+use hotkeys::Hotkey;
+use machine::{Halt, Machine};
+use pace::Pace;
+use palette::Palette;
+use video::VideoSink;
+
+/// Timers and the screen are updated at 60Hz.
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+fn read_file(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|error| {
+        eprintln!("Couldn't read {:?}: {}", path, error);
+        process::exit(1);
+    })
 }
 
 fn main() {
-    let roms_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
-    let args: Vec<String> = std::env::args().collect();
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Run(options) => run(*options, cli.config),
+        Command::Disasm { rom, start, source } => {
+            for line in disasm::disassemble_program(&read_file(&rom), start, !source) {
+                println!("{}", line);
+            }
+        }
+        Command::Asm { source, output, start } => {
+            let text = String::from_utf8_lossy(&read_file(&source)).into_owned();
+            let program = asm::assemble(&text, start).unwrap_or_else(|error| {
+                eprintln!("{:?}: {}", source, error);
+                process::exit(1);
+            });
+
+            let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+            fs::write(&output, &program).expect("Couldn't write ROM!");
+            println!("Assembled {} bytes into {:?}", program.len(), output);
+        }
+        Command::Info { rom, platform, start } => {
+            let platform = cpu::Platform::from_name(&platform).expect("Unknown platform!");
+            let start = start.unwrap_or_else(|| platform.start());
+            let buffer = read_file(&rom);
+            let hash = cpu::hash_program(&buffer);
+            let end = start as usize + buffer.len();
+            println!("SHA-1:   {}", hash);
+            println!("Size:    {} bytes", buffer.len());
+            println!("Loaded:  0x{:03X}-0x{:03X}", start, end);
+            if end > platform.memory_size() {
+                println!("Warning: doesn't fit in the 0x{:X} bytes of memory", platform.memory_size());
+            }
 
-    let mut chip8 =  cpu::Chip8::new();
+            let (config, _) = load_config(cli.config);
+            match load_database(&config).lookup(&hash) {
+                Some(info) => print_rom_info(&info),
+                None => println!("Not found in the ROM database"),
+            }
+        }
+    }
+}
 
-    let rom = if args.len() > 1{
-        roms_path.join(&args[1])
-    }else{
-        roms_path.join("tests/test_opcode.ch8")
+fn load_config(path: Option<PathBuf>) -> (Config, Option<PathBuf>) {
+    let path = path.or_else(Config::default_path);
+    let config = match &path {
+        Some(path) => Config::load(path).unwrap_or_else(|error| {
+            eprintln!("Using the default configuration: {}", error);
+            Config::default()
+        }),
+        None => Config::default(),
     };
+    (config, path)
+}
 
-    println!("Loading file {:?}", rom);
-    let mut file = File::open(rom)
-        .expect("Couldn't open file!");
-    //
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)
-        .expect("Couldn't read to buffer!");
-
-    chip8.initialize(0x200);
-    chip8.load_program(buffer);
-
-    let mut renderer = graphics::Renderer::new();
-    let mut running = true;
-    while running{
-        for event in renderer.poll_events(){
-            match event{
-                Event::WindowEvent{ event, ..} => match event{
-                    WindowEvent::CloseRequested => running = false,
-                    WindowEvent::Resized(..) => chip8.draw_flag = true,
-                    WindowEvent::KeyboardInput{ input, .. } => match input{
-                        KeyboardInput { virtual_keycode, state, ..} =>{
-                            if let Some(keycode) = virtual_keycode{
-                                let keydown = keyboard_to_keypad(keycode);
-                                if keydown != -1 {
-                                    if state == ElementState::Pressed{
-                                        chip8.set_key(keydown as u8, 1);
-                                    }else{
-                                        chip8.set_key(keydown as u8, 0);
-                                    }
-                                }
-                            }
-                        }
-                    },
-                    _ => (),
+fn load_database(config: &Config) -> Database {
+    match &config.emulation.database {
+        Some(dir) => Database::load(dir).unwrap_or_else(|error| {
+            eprintln!("Using the bundled ROM database: {}", error);
+            Database::bundled()
+        }),
+        None => Database::bundled(),
+    }
+}
+
+fn print_rom_info(info: &RomInfo) {
+    println!("Title:   {}", info.title);
+    if !info.authors.is_empty() {
+        println!("Authors: {}", info.authors.join(", "));
+    }
+    if let Some(release) = &info.release {
+        println!("Release: {}", release);
+    }
+    if let Some(description) = &info.description {
+        println!("About:   {}", description);
+    }
+    if let Some(platform) = &info.platform {
+        println!("Machine: {}", platform);
+    }
+    if let Some(speed) = info.hints.speed {
+        println!("Speed:   {} instructions per frame", speed);
+    }
+    for (hint, key) in &info.hints.keys {
+        println!("Key:     {} = {:X}", hint, key);
+    }
+}
+
+fn run(options: RunOptions, config_path: Option<PathBuf>) {
+    println!("Loading file {:?}", options.rom);
+    let rom = loader::load(&options.rom).unwrap_or_else(|error| {
+        eprintln!("Couldn't load {:?}: {}", options.rom, error);
+        process::exit(1);
+    });
+
+    let hash = cpu::hash_program(&rom.program);
+
+    let (mut config, config_path) = load_config(config_path);
+    let mut hints = rom.hints;
+    if let Some(info) = load_database(&config).lookup(&hash) {
+        print_rom_info(&info);
+        hints = hints.or(info.hints);
+    }
+
+    if options.save {
+        config.remember(&hash, &options);
+        match &config_path {
+            Some(path) => config.save(path).unwrap_or_else(|error| eprintln!("Couldn't save: {}", error)),
+            None => eprintln!("Couldn't save: no configuration directory"),
+        }
+    }
+    let settings = config.resolve(&hash, &hints, &options, rom.program.len()).unwrap_or_else(|error| {
+        eprintln!("Invalid configuration: {}", error);
+        process::exit(1);
+    });
+    let mut machine = create_machine(&options, &config, &settings, rom.program);
+
+    let video = if options.headless { Video::None } else { options.video };
+    match video {
+        Video::Window => {
+            let geometry = run_window(&mut *machine, &options, &settings);
+            if let (Some(geometry), Some(path)) = (geometry, &config_path) {
+                if config.window.remember(geometry) {
+                    config.save(path).unwrap_or_else(|error| eprintln!("Couldn't save the window geometry: {}", error));
+                }
+            }
+        }
+        Video::Terminal => {
+            let mut tui = tui::Tui::new().unwrap_or_else(|error| {
+                eprintln!("Couldn't set the terminal up: {}", error);
+                process::exit(1);
+            });
+            run_frames(&mut *machine, &options, &settings, &mut tui, true, |tui, machine| tui.poll(machine, &settings));
+        }
+        Video::Png => {
+            let preset = postfx::load(&settings.window.shader).unwrap_or_else(|error| {
+                eprintln!("Couldn't load shader {}: {}", settings.window.shader, error);
+                process::exit(1);
+            });
+            let filter = preset.persistence().map(postfx::PersistenceFilter::new);
+            let mut sink = video::PngSink::new(options.output.clone(), filter).unwrap_or_else(|error| {
+                eprintln!("Couldn't create {:?}: {}", options.output, error);
+                process::exit(1);
+            });
+            run_frames(&mut *machine, &options, &settings, &mut sink, false, |_, _| Vec::new());
+        }
+        Video::None => {
+            run_frames(&mut *machine, &options, &settings, &mut video::NullSink, false, |_, _| Vec::new());
+            let frame = machine.frame();
+            for y in 0..frame.height {
+                let line: String = (0..frame.width).map(|x| if frame.pixel(x, y) != 0 { '█' } else { ' ' }).collect();
+                println!("{}", line.trim_end());
+            }
+        }
+    }
+}
+
+/// The machine picked with `--machine`, with the program loaded.
+fn create_machine(options: &RunOptions, config: &Config, settings: &Settings, program: Vec<u8>) -> Box<dyn Machine> {
+    match options.machine {
+        MachineType::Chip8 => {
+            let mut chip8 = cpu::Chip8::new();
+            chip8.set_platform(settings.platform);
+            chip8.initialize(settings.start);
+            if let Some(seed) = options.seed {
+                chip8.seed(seed);
+            }
+            chip8.set_font(settings.font.clone(), settings.font_address);
+            chip8.load_program(program).unwrap_or_else(|error| {
+                eprintln!("Couldn't load {:?}: {}", options.rom, error);
+                process::exit(1);
+            });
+            chip8.set_quirks(settings.quirks);
+            chip8.set_timing(settings.timing);
+            chip8.set_machine_code(settings.machine_code, settings.routines.clone());
+            chip8.set_diagnostics(settings.diagnostics);
+            chip8.set_display_mode(settings.display);
+            Box::new(chip8)
+        }
+        MachineType::Vip => {
+            let path = options.interpreter.as_ref().or(config.emulation.interpreter.as_ref()).unwrap_or_else(|| {
+                eprintln!("The VIP runs the original CHIP-8 interpreter, give it with --interpreter");
+                process::exit(1);
+            });
+            let interpreter = read_file(path);
+            let monitor = options.monitor.as_ref().or(config.emulation.monitor.as_ref()).map(|path| read_file(path));
+            let vip = vip::Vip::new(&interpreter, monitor, &program).unwrap_or_else(|error| {
+                eprintln!("Couldn't start the VIP: {}", error);
+                process::exit(1);
+            });
+            Box::new(vip)
+        }
+    }
+}
+
+/// Runs a frame, beeping when the buzzer starts.
+fn emulate_frame(machine: &mut dyn Machine, speed: u32, settings: &Settings, beeping: &mut bool, sink: &mut dyn VideoSink) -> Result<(), Halt> {
+    let result = machine.emulate_frame(speed);
+
+    if machine.is_beeping() && !*beeping && !settings.mute {
+        sink.message("BEEP!");
+    }
+    *beeping = machine.is_beeping();
+    result
+}
+
+/// Runs the emulation until `--frames` are done or the front-end quits,
+/// handing every frame to `sink`. `poll` is called before each frame with
+/// the sink, for front-ends that also deliver input, and returns the hotkeys pressed.
+/// Unpaced runs go as fast as possible, for CI and scripted testing.
+fn run_frames<S, F>(machine: &mut dyn Machine, options: &RunOptions, settings: &Settings, sink: &mut S, paced: bool, mut poll: F)
+where
+    S: VideoSink,
+    F: FnMut(&mut S, &mut dyn Machine) -> Vec<Hotkey>,
+{
+    let mut palette = settings.palette.clone();
+    let mut pace = Pace::new(settings.speed);
+    let mut beeping = false;
+    let mut frame = 0;
+    let mut presented = Instant::now();
+    let mut recorder = options.record.as_ref().and_then(|path| start_recording(path, options, sink));
+    let mut audio = options.audio.as_ref().and_then(|path| match File::create(path) {
+        Ok(file) => Some(BufWriter::new(file)),
+        Err(error) => {
+            sink.message(&format!("Couldn't write the sound to {:?}: {}", path, error));
+            None
+        }
+    });
+    'frames: while !matches!(options.frames, Some(frames) if frame >= frames) {
+        let frame_start = Instant::now();
+
+        for hotkey in poll(sink, machine) {
+            if pace.apply(hotkey) {
+                continue;
+            }
+            match hotkey {
+                Hotkey::Quit => break 'frames,
+                Hotkey::NextPalette => {
+                    palette = palette.next();
+                    sink.message(&format!("Palette: {}", palette.name));
+                }
+                Hotkey::Record => match recorder.take() {
+                    Some(recording) => stop_recording(recording, sink),
+                    None => {
+                        let extension = options.record.as_ref().and_then(|path| path.extension());
+                        let extension = extension.map_or("gif".into(), |extension| extension.to_string_lossy());
+                        recorder = start_recording(&record::default_path(&extension), options, sink);
+                    }
                 },
+                Hotkey::Screenshot => {
+                    let path = record::default_path("png");
+                    screenshot(machine, &path, &palette, frame, options, sink);
+                }
                 _ => (),
             }
         }
 
-        chip8.emulate_cycle();
+        if pace.run_frame() {
+            let result = emulate_frame(machine, pace.speed, settings, &mut beeping, sink);
+            frame += 1;
+            if let Some(file) = &mut audio {
+                // silence where nothing plays, so the sound stays in time with the frames
+                let mut samples = machine.audio().to_vec();
+                samples.resize((machine::AUDIO_RATE / 60) as usize, 0x80);
+                if let Err(error) = file.write_all(&samples) {
+                    sink.message(&format!("Couldn't write the sound: {}", error));
+                    audio = None;
+                }
+            }
+            match result {
+                Ok(()) => (),
+                // nobody could resume an unpaced run
+                Err(Halt::Break(reason)) if paced => {
+                    sink.message(&format!("Break: {}", reason));
+                    pace.pause();
+                }
+                Err(Halt::Break(reason)) => {
+                    sink.message(&format!("Break: {}", reason));
+                    break 'frames;
+                }
+                Err(Halt::Error(error)) => {
+                    sink.message(&format!("Stopped: {}", error));
+                    break 'frames;
+                }
+            }
+        }
 
-        if chip8.draw_flag{
-            // println!("Drawing");
-            renderer.new_frame();
-            renderer.clear_screen();
+        // in turbo, only show as many frames as the screen can
+        let turbo = paced && pace.turbo();
+        if !turbo || presented.elapsed() >= FRAME_DURATION {
+            let frame = machine.frame();
+            sink.status(&pace.status());
+            sink.present(&frame, &palette.colors);
+            presented = Instant::now();
 
-            let mut display: Vec<Vec<graphics::Color>> = vec![vec![(0, 0, 0); 64]; 32];
-            for (n, pixel) in chip8.get_display().iter().enumerate(){
-                if *pixel != 0{
-                    let i = n / 64;
-                    let j = n % 64;
-                    display[i][j] = (255, 255, 255);
+            if let Some(recording) = &mut recorder {
+                if let Err(error) = recording.record(&frame, &palette.colors) {
+                    sink.message(&format!("Couldn't record: {}", error));
+                    recorder = None;
                 }
             }
-            renderer.draw_screen(display);
-            renderer.finish_frame();
+        }
 
-            chip8.draw_flag = false;
+        if paced && !turbo {
+            if let Some(remaining) = pace.frame_duration(FRAME_DURATION).checked_sub(frame_start.elapsed()) {
+                thread::sleep(remaining);
+            }
         }
     }
+
+    if let Some(recording) = recorder {
+        stop_recording(recording, sink);
+    }
+    if let Some(path) = &options.screenshot {
+        screenshot(machine, path, &palette, frame, options, sink);
+    }
+}
+
+/// Saves the display as it is shown, and with `--screenshot-info` what it
+/// takes to get it again: the ROM, where it was running and after how many frames.
+fn screenshot(machine: &dyn Machine, path: &Path, palette: &Palette, frame: u64, options: &RunOptions, sink: &mut dyn VideoSink) {
+    let mut text = Vec::new();
+    if options.screenshot_info {
+        text.push(("ROM SHA-1", machine.rom_hash().to_string()));
+        text.push(("PC", format!("{:04X}", machine.registers().pc)));
+        text.push(("Frame", frame.to_string()));
+    }
+    match machine.frame().save_png(path, &palette.colors, options.screenshot_scale, &text) {
+        Ok(()) => sink.message(&format!("Saved {:?}", path)),
+        Err(error) => sink.message(&format!("Couldn't save the screenshot: {}", error)),
+    }
+}
+
+fn start_recording(path: &Path, options: &RunOptions, sink: &mut dyn VideoSink) -> Option<record::Recorder> {
+    match record::Recorder::create(path, options.record_scale) {
+        Ok(recording) => {
+            sink.message(&format!("Recording to {:?}", recording.path()));
+            Some(recording)
+        }
+        Err(error) => {
+            sink.message(&format!("Couldn't record: {}", error));
+            None
+        }
+    }
+}
+
+fn stop_recording(recording: record::Recorder, sink: &mut dyn VideoSink) {
+    let path = recording.path().to_path_buf();
+    match recording.finish() {
+        Ok(()) => sink.message(&format!("Saved {:?}", path)),
+        Err(error) => sink.message(&format!("Couldn't save the recording: {}", error)),
+    }
+}
+
+/// Returns the geometry of the window when closed, see `Renderer::geometry`.
+fn run_window(machine: &mut dyn Machine, options: &RunOptions, settings: &Settings) -> Option<(u32, u32, (i32, i32))> {
+    let mut events_loop = glutin::EventsLoop::new();
+    let mut renderer = graphics::Renderer::new(&settings.window, settings.scaling, &events_loop);
+    renderer.set_shader(&settings.window.shader).unwrap_or_else(|error| {
+        eprintln!("Couldn't load shader {}: {}", settings.window.shader, error);
+        process::exit(1);
+    });
+    let mut gamepads = gamepad::Gamepads::new(settings.gamepad.clone());
+
+    run_frames(machine, options, settings, &mut renderer, true, |renderer, machine| {
+        let mut hotkeys = Vec::new();
+        events_loop.poll_events(|event| {
+            if let Event::WindowEvent{ event, ..} = event{
+                match event{
+                    WindowEvent::CloseRequested => hotkeys.push(Hotkey::Quit),
+                    WindowEvent::KeyboardInput{ input: KeyboardInput { virtual_keycode: Some(keycode), state, ..}, .. } => {
+                        let name = format!("{:?}", keycode);
+                        if let Some(hotkey) = hotkeys::hotkey(&name, state == ElementState::Pressed) {
+                            match hotkey {
+                                Hotkey::NextShader => renderer.cycle_shader(),
+                                Hotkey::ToggleFullscreen => renderer.toggle_fullscreen(),
+                                _ => hotkeys.push(hotkey),
+                            }
+                        } else if let Some(key) = settings.keypad_key(&name) {
+                            if state == ElementState::Pressed{
+                                machine.set_key(key, 1);
+                            }else{
+                                machine.set_key(key, 0);
+                            }
+                        } else if let Some(key) = settings.second_keypad_key(&name) {
+                            machine.set_second_key(key, (state == ElementState::Pressed) as u8);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        });
+
+        for (key, state) in gamepads.poll(){
+            machine.set_key(key, state);
+        }
+        hotkeys
+    });
+    renderer.geometry()
 }
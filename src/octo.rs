@@ -0,0 +1,693 @@
+use std::collections::HashMap;
+
+/// Compiles Octo source (https://github.com/JohnEarnest/Octo), the language
+/// `.o8` files and Octo cartridges are written in, into a program loaded at `0x200`.
+///
+/// Covers labels, `:const`, `:alias`, `:org`, `:unpack`, `:next`, `:call`,
+/// `:byte`, `:macro` (without `CALLS`), `:calc`, every CHIP-8 statement, `i := bighex`,
+/// `plane` and the `if`/`then`, `if`/`begin`/`else`/`end` and `loop`/`while`/`again` control
+/// flow on `==`, `!=`, `key` and `-key` conditions. Programs still have to fit in 4K.
+/// `:stringmode`, `:pointer`, `:assert`, the comparison conditions (`<`, `>`...) and
+/// the other SUPER-CHIP and XO-CHIP statements, which the emulator doesn't run, are reported as errors.
+pub fn compile(source: &str) -> Result<Vec<u8>, String> {
+    let mut compiler = Compiler::new(source);
+    compiler.run()?;
+    compiler.finish()
+}
+
+const START: usize = 0x200;
+
+enum Block {
+    /// Address of the jump to patch when the `if` is done, or when `else` starts.
+    If(usize),
+    /// Where the loop starts, and the jumps out of it left by `while`.
+    Loop(usize, Vec<usize>),
+}
+
+/// Macro invocations expanding into more macros, beyond this, are taken for endless recursion.
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// A `:macro`, its arguments and the tokens they are replaced in.
+struct Macro<'a> {
+    arguments: Vec<&'a str>,
+    body: Vec<(usize, &'a str)>,
+}
+
+struct Compiler<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    position: usize,
+    memory: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i32>,
+    aliases: HashMap<String, u16>,
+    macros: HashMap<&'a str, Macro<'a>>,
+    expansions: usize,
+    /// 12-bit operands waiting for a label defined further down.
+    fixups: Vec<(usize, String, usize)>,
+    blocks: Vec<Block>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut tokens = Vec::new();
+        for (n, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            tokens.extend(line.split_whitespace().map(|token| (n + 1, token)));
+        }
+
+        Self {
+            tokens,
+            position: 0,
+            memory: Vec::new(),
+            here: START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position.saturating_sub(1))
+            .map_or(0, |(line, _)| *line)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line(), message))
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        match self.tokens.get(self.position) {
+            Some((_, token)) => {
+                self.position += 1;
+                Ok(token)
+            }
+            None => self.error("unexpected end of file".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|(_, token)| *token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected `{}`, found `{}`", expected, token));
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), String> {
+        let offset = match self.here.checked_sub(START) {
+            Some(offset) if self.here < 0x1000 => offset,
+            _ => return self.error(format!("address 0x{:X} is outside of memory", self.here)),
+        };
+        if self.memory.len() <= offset {
+            self.memory.resize(offset + 1, 0);
+        }
+        self.memory[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), String> {
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte(opcode as u8)
+    }
+
+    fn register_value(&self, token: &str) -> Option<u16> {
+        let lower = token.to_ascii_lowercase();
+        if let Some(register) = self.aliases.get(&lower) {
+            return Some(*register);
+        }
+        match lower.strip_prefix('v') {
+            Some(x) if x.len() == 1 => u16::from_str_radix(x, 16).ok(),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        match self.register_value(token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("expected a register, found `{}`", token)),
+        }
+    }
+
+    fn number_value(&self, token: &str) -> Option<i32> {
+        if let Some(value) = self.constants.get(token) {
+            return Some(*value);
+        }
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i32::from_str_radix(binary, 2).ok()?
+        } else {
+            digits.parse().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    /// A byte operand, negative numbers wrap around like Octo does.
+    fn byte(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        match self.number_value(token) {
+            Some(value) if (-128..=255).contains(&value) => Ok((value & 0xFF) as u16),
+            _ => self.error(format!("expected a byte, found `{}`", token)),
+        }
+    }
+
+    /// A 12-bit address, which may be a label defined later on.
+    fn address(&mut self, opcode: u16) -> Result<(), String> {
+        let token = self.next()?;
+        if let Some(address) = self.labels.get(token) {
+            return self.emit(opcode | *address as u16);
+        }
+        match self.number_value(token) {
+            Some(value) if (0..=0xFFF).contains(&value) => self.emit(opcode | value as u16),
+            Some(_) => self.error(format!("`{}` doesn't fit in 12 bits", token)),
+            None => {
+                self.fixups.push((self.here, token.to_string(), self.line()));
+                self.emit(opcode)
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        // programs start at `main`, jumped to unless it's the very first label
+        let starts_with_main = matches!(self.tokens.as_slice(), [(_, ":"), (_, "main"), ..]);
+        if !starts_with_main {
+            self.fixups.push((self.here, "main".to_string(), 1));
+            self.emit(0x1000)?;
+        }
+
+        while let Some(token) = self.peek() {
+            self.position += 1;
+            self.statement(token)?;
+        }
+
+        if !self.blocks.is_empty() {
+            return self.error("missing `end` or `again`".to_string());
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: &'a str) -> Result<(), String> {
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name.to_string(), self.here).is_some() {
+                    return self.error(format!("label `{}` defined twice", name));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                match self.number_value(value) {
+                    Some(value) => {
+                        self.constants.insert(name.to_string(), value);
+                    }
+                    None => return self.error(format!("invalid constant `{}`", value)),
+                }
+            }
+            ":alias" => {
+                let name = self.next()?.to_ascii_lowercase();
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":org" => {
+                let token = self.next()?;
+                match self.number_value(token) {
+                    Some(address) if (START as i32..0x1000).contains(&address) => {
+                        self.here = address as usize
+                    }
+                    _ => return self.error(format!("invalid address `{}`", token)),
+                }
+            }
+            ":unpack" => {
+                let high = self.byte()?;
+                let label = self.next()?;
+                let address = match self.labels.get(label) {
+                    Some(address) => *address as u16,
+                    None => return self.error(format!("`{}` must be defined before `:unpack`", label)),
+                };
+                self.emit(0x6000 | (high << 4 | address >> 8) & 0xFF)?;
+                self.emit(0x6100 | address & 0xFF)?;
+            }
+            ":next" => {
+                // the second byte of the next instruction, for code that rewrites its operand
+                let name = self.next()?;
+                if self.labels.insert(name.to_string(), self.here + 1).is_some() {
+                    return self.error(format!("label `{}` defined twice", name));
+                }
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut arguments = Vec::new();
+                loop {
+                    match self.next()? {
+                        "{" => break,
+                        argument => arguments.push(argument),
+                    }
+                }
+                let body = self.braces()?;
+                self.macros.insert(name, Macro { arguments, body });
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.expression()?;
+                self.expect("}")?;
+                self.constants.insert(name.to_string(), value.floor() as i32);
+            }
+            ":call" => self.address(0x2000)?,
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte as u8)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "return" | ";" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            // the SUPER-CHIP and XO-CHIP instructions the emulator doesn't run
+            "hires" | "lores" | "scroll-right" | "scroll-left" | "scroll-down" | "scroll-up" | "exit" | "saveflags"
+            | "loadflags" | "audio" | "pitch" => return self.unsupported(token),
+            // XO-CHIP
+            "plane" => {
+                let n = self.byte()?;
+                if n > 0x3 {
+                    return self.error(format!("there are planes 0 to 3, not {}", n));
+                }
+                self.emit(0xF001 | n << 8)?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | x << 8)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    // XO-CHIP ranges of registers
+                    return self.unsupported(&format!("{} vx - vy", token));
+                }
+                self.emit(if token == "save" { 0xF055 } else { 0xF065 } | x << 8)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.byte()?;
+                if n > 0xF {
+                    return self.error(format!("sprites are at most 15 rows, not {}", n));
+                }
+                self.emit(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" => self.address(0x1000)?,
+            "jump0" => self.address(0xB000)?,
+            "native" => self.address(0x0000)?,
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(if token == "delay" { 0xF015 } else { 0xF018 } | x << 8)?;
+            }
+            "i" | "I" => self.index()?,
+            "if" => {
+                let skip = self.condition()?;
+                match self.next()? {
+                    "then" => self.emit(skip)?,
+                    "begin" => {
+                        self.emit(invert(skip))?;
+                        self.blocks.push(Block::If(self.here));
+                        self.emit(0x1000)?;
+                    }
+                    other => return self.error(format!("expected `then` or `begin`, found `{}`", other)),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If(jump)) => {
+                    let end = self.here;
+                    self.emit(0x1000)?;
+                    self.patch(jump, self.here);
+                    self.blocks.push(Block::If(end));
+                }
+                _ => return self.error("`else` without `if ... begin`".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump)) => self.patch(jump, self.here),
+                _ => return self.error("`end` without `if ... begin`".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop(self.here, Vec::new())),
+            "while" => {
+                let skip = self.condition()?;
+                self.emit(invert(skip))?;
+                let jump = self.here;
+                self.emit(0x1000)?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop(..))) {
+                    Some(Block::Loop(_, exits)) => exits.push(jump),
+                    _ => return self.error("`while` outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop(start, exits)) => {
+                    self.emit(0x1000 | start as u16)?;
+                    for jump in exits {
+                        self.patch(jump, self.here);
+                    }
+                }
+                _ => return self.error("`again` without `loop`".to_string()),
+            },
+            ":stringmode" | ":pointer" | ":assert" => return self.unsupported(token),
+            _ => {
+                if let Some(x) = self.register_value(token) {
+                    self.assignment(x)?;
+                } else if let Some(value) = self.number_value(token) {
+                    if !(-128..=255).contains(&value) {
+                        return self.error(format!("`{}` doesn't fit in a byte", token));
+                    }
+                    self.emit_byte(value as u8)?;
+                } else if token.starts_with(':') {
+                    return self.unsupported(token);
+                } else if self.macros.contains_key(token) {
+                    self.expand(token)?;
+                } else {
+                    // any other name is a subroutine call
+                    self.position -= 1;
+                    self.address(0x2000)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), String> {
+        match self.next()? {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.position += 1;
+                    let x = self.register()?;
+                    self.emit(0xF029 | x << 8)
+                }
+                Some("bighex") => {
+                    self.position += 1;
+                    let x = self.register()?;
+                    self.emit(0xF030 | x << 8)
+                }
+                Some("long") => self.unsupported("i := long"),
+                _ => self.address(0xA000),
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | x << 8)
+            }
+            other => self.error(format!("unknown operator `i {}`", other)),
+        }
+    }
+
+    fn assignment(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let operand = self.peek().unwrap_or("");
+        let y = self.register_value(operand);
+
+        let opcode = match (operator, y) {
+            (":=", _) if operand == "key" => 0xF00A | x << 8,
+            (":=", _) if operand == "delay" => 0xF007 | x << 8,
+            (":=", _) if operand == "random" => {
+                self.position += 1;
+                0xC000 | x << 8 | self.byte()?
+            }
+            (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+            (":=", None) => {
+                let byte = self.byte()?;
+                return self.emit(0x6000 | x << 8 | byte);
+            }
+            ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("+=", None) => {
+                let byte = self.byte()?;
+                return self.emit(0x7000 | x << 8 | byte);
+            }
+            ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+            ("-=", None) => {
+                let byte = self.byte()?;
+                return self.emit(0x7000 | x << 8 | (0x100 - byte) & 0xFF);
+            }
+            ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+            (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+            _ => return self.error(format!("unknown operation `v{:X} {} {}`", x, operator, operand)),
+        };
+
+        self.position += 1;
+        self.emit(opcode)
+    }
+
+    /// Returns the instruction that skips the next one unless the condition holds,
+    /// which is what `if ... then` compiles to.
+    fn condition(&mut self) -> Result<u16, String> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        match operator {
+            "key" => return Ok(0xE0A1 | x << 8),
+            "-key" => return Ok(0xE09E | x << 8),
+            _ => (),
+        }
+
+        let operand = self.peek().unwrap_or("");
+        let opcode = match (operator, self.register_value(operand)) {
+            ("==", Some(y)) => 0x9000 | x << 8 | y << 4,
+            ("!=", Some(y)) => 0x5000 | x << 8 | y << 4,
+            ("==", None) => return Ok(0x4000 | x << 8 | self.byte()?),
+            ("!=", None) => return Ok(0x3000 | x << 8 | self.byte()?),
+            _ => return self.error(format!("`{}` conditions are not supported", operator)),
+        };
+        self.position += 1;
+        Ok(opcode)
+    }
+
+    fn unsupported<T>(&self, token: &str) -> Result<T, String> {
+        self.error(format!(
+            "`{}` is not supported, only the CHIP-8 statements, `bighex`, `plane`, macros and `:calc` are",
+            token
+        ))
+    }
+
+    /// The tokens up to the `}` closing a `{` just read, nested braces included.
+    fn braces(&mut self) -> Result<Vec<(usize, &'a str)>, String> {
+        let start = self.position;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => (),
+            }
+        }
+        Ok(self.tokens[start..self.position - 1].to_vec())
+    }
+
+    /// Replaces a macro invocation with the body of the macro, its arguments filled in.
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return self.error(format!("`{}` expands forever", name));
+        }
+        let count = self.macros[name].arguments.len();
+        let mut arguments = Vec::with_capacity(count);
+        for _ in 0..count {
+            arguments.push(self.next()?);
+        }
+
+        // errors in the expansion are reported on the line of the invocation
+        let line = self.line();
+        let definition = &self.macros[name];
+        let body: Vec<(usize, &'a str)> = definition
+            .body
+            .iter()
+            .map(|(_, token)| match definition.arguments.iter().position(|argument| argument == token) {
+                Some(n) => (line, arguments[n]),
+                None => (line, *token),
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    /// A `:calc` expression: Octo evaluates operators right to left, without precedence.
+    fn expression(&mut self) -> Result<f64, String> {
+        let left = self.term()?;
+        let operator = match self.peek() {
+            Some("}") | Some(")") | None => return Ok(left),
+            Some(operator) => operator,
+        };
+        self.position += 1;
+        let right = self.expression()?;
+        let (a, b) = (left as i64, right as i64);
+        Ok(match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "<" => (left < right) as i32 as f64,
+            ">" => (left > right) as i32 as f64,
+            "<=" => (left <= right) as i32 as f64,
+            ">=" => (left >= right) as i32 as f64,
+            "==" => (left == right) as i32 as f64,
+            "!=" => (left != right) as i32 as f64,
+            _ => return self.error(format!("unknown operator `{}`", operator)),
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        let value = match token {
+            "(" => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.term()?,
+            "~" => !(self.term()? as i64) as f64,
+            "!" => (self.term()? == 0.0) as i32 as f64,
+            "abs" => self.term()?.abs(),
+            "sqrt" => self.term()?.sqrt(),
+            "sin" => self.term()?.sin(),
+            "cos" => self.term()?.cos(),
+            "tan" => self.term()?.tan(),
+            "exp" => self.term()?.exp(),
+            "log" => self.term()?.ln(),
+            "sign" => self.term()?.signum(),
+            "ceil" => self.term()?.ceil(),
+            "floor" => self.term()?.floor(),
+            "@" => {
+                let address = self.term()? as usize;
+                let offset = address.wrapping_sub(START);
+                self.memory.get(offset).copied().unwrap_or(0) as f64
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => match (self.labels.get(token), self.number_value(token)) {
+                (Some(address), _) => *address as f64,
+                (None, Some(value)) => value as f64,
+                // fractions, which only `:calc` has
+                (None, None) => match token.parse() {
+                    Ok(value) => value,
+                    Err(_) => return self.error(format!("unknown value `{}` in `:calc`", token)),
+                },
+            },
+        };
+        Ok(value)
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        let offset = at - START;
+        self.memory[offset] = 0x10 | (target >> 8) as u8 & 0xF;
+        self.memory[offset + 1] = target as u8;
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        for (at, label, line) in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&label) {
+                Some(address) => *address,
+                None => return Err(format!("line {}: unknown label `{}`", line, label)),
+            };
+            let offset = at - START;
+            self.memory[offset] |= (address >> 8) as u8 & 0xF;
+            self.memory[offset + 1] = address as u8;
+        }
+
+        Ok(self.memory)
+    }
+}
+
+/// Swaps a skip instruction for the one with the opposite condition.
+fn invert(skip: u16) -> u16 {
+    match skip & 0xF000 {
+        0x3000 => skip ^ 0x7000, // SE  <-> SNE byte
+        0x4000 => skip ^ 0x7000,
+        0x5000 => skip ^ 0xC000, // SE  <-> SNE register
+        0x9000 => skip ^ 0xC000,
+        _ => skip ^ 0x003F, // SKP <-> SKNP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        let program = compile(source).unwrap();
+        program.chunks(2).map(|word| (word[0] as u16) << 8 | word[1] as u16).collect()
+    }
+
+    #[test]
+    fn macros_expand_with_their_arguments() {
+        let source = "
+            :macro set register value { register := value }
+            :macro twice a b { set a b set a b }
+            : main
+            twice v3 7
+            set v1 0x22
+        ";
+        // `main` isn't the first label, so it's jumped to
+        assert_eq!(words(source), [0x1202, 0x6307, 0x6307, 0x6122]);
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left() {
+        let source = "
+            :const WIDTH 8
+            :calc STRIDE { WIDTH * 2 + 1 }
+            :calc EDGE { ( WIDTH * 2 ) + 1 }
+            :calc AFTER { ( HERE - 0x200 ) + 2 }
+            : main
+            v0 := STRIDE
+            v1 := EDGE
+            v2 := AFTER
+        ";
+        // after the jump to `main`
+        assert_eq!(words(source), [0x1202, 0x6018, 0x6111, 0x6204]);
+    }
+
+    #[test]
+    fn planes_and_big_digits() {
+        assert_eq!(words(": main plane 3 i := bighex v2 plane 1"), [0xF301, 0xF230, 0xF101]);
+    }
+
+    #[test]
+    fn unsupported_statements_say_what_is() {
+        let error = compile(": main :stringmode hex \"0123456789ABCDEF\" { }").unwrap_err();
+        assert!(error.starts_with("line 1: `:stringmode` is not supported"), "{}", error);
+        assert!(compile(":macro loop-forever { loop-forever } : main loop-forever").unwrap_err().contains("expands forever"));
+        // the emulator doesn't run these, rather than compiling them into something else
+        for statement in ["hires", "scroll-down 4", "saveflags v7", "audio", "pitch := v2", "save v1 - v4", "i := long main"].iter() {
+            let error = compile(&format!(": main {}", statement)).unwrap_err();
+            assert!(error.contains("is not supported"), "{}: {}", statement, error);
+        }
+    }
+}
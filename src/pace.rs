@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use crate::hotkeys::Hotkey;
+
+/// Slow motion steps, as how many times longer a frame lasts.
+const SLOWDOWNS: [u32; 3] = [1, 2, 4];
+
+/// How fast the emulation runs, changed with hotkeys while playing:
+/// pause, frame advance, slow motion, turbo and instructions per frame.
+pub struct Pace {
+    /// Instructions executed per frame.
+    pub speed: u32,
+    paused: bool,
+    /// Runs a single frame while paused.
+    advance: bool,
+    slowdown: usize,
+    /// Runs as fast as possible, while the key is held.
+    turbo: bool,
+
+    /// Frames emulated since `since`, to tell the frame rate.
+    frames: u32,
+    since: Instant,
+    fps: u32,
+}
+
+impl Pace {
+    pub fn new(speed: u32) -> Self {
+        Self {
+            speed,
+            paused: false,
+            advance: false,
+            slowdown: 0,
+            turbo: false,
+            frames: 0,
+            since: Instant::now(),
+            fps: 0,
+        }
+    }
+
+    /// Handles the hotkeys about speed, returning false for the other ones.
+    pub fn apply(&mut self, hotkey: Hotkey) -> bool {
+        match hotkey {
+            Hotkey::Pause => self.paused = !self.paused,
+            Hotkey::FrameAdvance => {
+                self.paused = true;
+                self.advance = true;
+            }
+            Hotkey::SlowMotion => self.slowdown = (self.slowdown + 1) % SLOWDOWNS.len(),
+            Hotkey::Turbo(held) => self.turbo = held,
+            Hotkey::SpeedUp => self.speed += 1,
+            Hotkey::SpeedDown => self.speed = self.speed.saturating_sub(1).max(1),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn turbo(&self) -> bool {
+        self.turbo
+    }
+
+    /// Whether the next frame should be emulated, counting it if so.
+    pub fn run_frame(&mut self) -> bool {
+        if self.paused && !self.advance {
+            return false;
+        }
+        self.advance = false;
+        self.frames += 1;
+        true
+    }
+
+    /// How long a frame lasts, one 60th of a second unless in slow motion.
+    pub fn frame_duration(&self, frame: Duration) -> Duration {
+        frame * SLOWDOWNS[self.slowdown]
+    }
+
+    /// The speed and the frame rate, like `10 ipf, 50%, 30 fps`.
+    pub fn status(&mut self) -> String {
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = (self.frames as f64 / elapsed.as_secs_f64()).round() as u32;
+            self.frames = 0;
+            self.since = Instant::now();
+        }
+
+        let mut status = format!("{} ipf, {}%", self.speed, 100 / SLOWDOWNS[self.slowdown]);
+        if self.paused {
+            status.push_str(", paused");
+        } else if self.turbo {
+            status.push_str(", turbo");
+        }
+        status.push_str(&format!(", {} fps", self.fps));
+        status
+    }
+}
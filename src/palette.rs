@@ -0,0 +1,47 @@
+use crate::graphics::{self, Color};
+
+/// The colours of the display: the background, then the pixels lit on the
+/// first plane, on the second plane (XO-CHIP) and on both planes (XO-CHIP).
+#[derive(Clone, Debug)]
+pub struct Palette {
+    /// `custom` once any colour is changed.
+    pub name: String,
+    pub colors: [Color; 4],
+}
+
+/// Built-in palettes, in the order they are cycled through.
+const PALETTES: [(&str, [&str; 4]); 5] = [
+    ("classic", ["000000", "FFFFFF", "AAAAAA", "555555"]),
+    ("amber", ["1A0F00", "FFB000", "B37B00", "FFD480"]),
+    ("green", ["001A00", "33FF33", "1F991F", "99FF99"]),
+    ("lcd", ["9BBC0F", "0F380F", "306230", "8BAC0F"]),
+    ("octo", ["996600", "FFCC00", "FF6600", "662200"]),
+];
+
+impl Palette {
+    pub const NAMES: [&'static str; 5] = ["classic", "amber", "green", "lcd", "octo"];
+
+    pub fn named(name: &str) -> Option<Self> {
+        let (name, colors) = PALETTES.iter().find(|(palette, _)| *palette == name)?;
+        let colors = colors.map(|color| graphics::parse_color(color).expect("Invalid built-in palette!"));
+        Some(Self {
+            name: name.to_string(),
+            colors,
+        })
+    }
+
+    /// The built-in palette after this one, the first one after a custom palette.
+    pub fn next(&self) -> Self {
+        let current = Self::NAMES.iter().position(|name| *name == self.name);
+        let next = current.map_or(0, |current| (current + 1) % Self::NAMES.len());
+        Self::named(Self::NAMES[next]).expect("Unknown built-in palette!")
+    }
+
+    /// Replaces one of the colours, see `colors`.
+    pub fn set(&mut self, index: usize, color: Color) {
+        if self.colors[index] != color {
+            self.colors[index] = color;
+            self.name = "custom".to_string();
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Post-processing presets: chains of fragment shaders run over the display
+/// before it reaches the window, described in TOML files like this one.
+///
+///```toml
+///[[passes]]
+///shader = "persistence" # a built-in shader, or a GLSL file next to the preset
+///scale = 1              # renders at the emulated resolution times this, or the window size if unset
+///filter = "nearest"     # how the pass samples its source, `nearest` or `linear`
+///parameters = { decay = 0.8 }
+///```
+/// Shaders are `#version 140` fragment shaders, drawing a quad with `v_tex_coords`
+/// and the uniforms:
+/// - `source`: the output of the previous pass, the display for the first one
+/// - `history`: the output of this very pass on the previous frame
+/// - `source_size`, `output_size`: in pixels
+/// - one float per entry of `parameters`
+#[derive(Deserialize, Default)]
+pub struct Preset {
+    #[serde(default)]
+    pub passes: Vec<Pass>,
+}
+
+#[derive(Deserialize)]
+pub struct Pass {
+    pub shader: String,
+    pub scale: Option<u32>,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub parameters: BTreeMap<String, f32>,
+    /// The GLSL of `shader`, once loaded.
+    #[serde(skip)]
+    pub source: String,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    #[default]
+    Nearest,
+    Linear,
+}
+
+const PERSISTENCE: &str = "
+    #version 140
+
+    uniform sampler2D source;
+    uniform sampler2D history;
+    uniform float decay = 0.8;
+
+    varying vec2 v_tex_coords;
+    void main() {
+        vec4 current = texture2D(source, v_tex_coords);
+        vec4 previous = texture2D(history, v_tex_coords) * decay;
+        gl_FragColor = max(current, previous);
+    }
+";
+
+const SCANLINES: &str = "
+    #version 140
+
+    uniform sampler2D source;
+    uniform vec2 source_size;
+    uniform float strength = 0.4;
+
+    varying vec2 v_tex_coords;
+    void main() {
+        vec4 color = texture2D(source, v_tex_coords);
+        float line = sin(v_tex_coords.y * source_size.y * 3.14159265);
+        gl_FragColor = color * (1.0 - strength * (1.0 - line * line));
+    }
+";
+
+const BLOOM: &str = "
+    #version 140
+
+    uniform sampler2D source;
+    uniform vec2 output_size;
+    uniform float strength = 0.35;
+    uniform float radius = 3.0;
+
+    varying vec2 v_tex_coords;
+    void main() {
+        vec4 color = texture2D(source, v_tex_coords);
+        vec2 step = radius / output_size;
+        vec4 glow = vec4(0.0);
+        for (int x = -2; x <= 2; x++) {
+            for (int y = -2; y <= 2; y++) {
+                glow += texture2D(source, v_tex_coords + vec2(x, y) * step);
+            }
+        }
+        gl_FragColor = color + glow / 25.0 * strength;
+    }
+";
+
+const CURVATURE: &str = "
+    #version 140
+
+    uniform sampler2D source;
+    uniform float amount = 0.08;
+
+    varying vec2 v_tex_coords;
+    void main() {
+        vec2 centered = v_tex_coords * 2.0 - 1.0;
+        centered *= 1.0 + amount * dot(centered.yx, centered.yx);
+        vec2 uv = centered * 0.5 + 0.5;
+        if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+            gl_FragColor = vec4(0.0, 0.0, 0.0, 1.0);
+        } else {
+            gl_FragColor = texture2D(source, uv);
+        }
+    }
+";
+
+const VIGNETTE: &str = "
+    #version 140
+
+    uniform sampler2D source;
+    uniform float strength = 0.35;
+
+    varying vec2 v_tex_coords;
+    void main() {
+        vec2 centered = v_tex_coords - 0.5;
+        float shade = 1.0 - strength * dot(centered, centered) * 4.0;
+        gl_FragColor = texture2D(source, v_tex_coords) * clamp(shade, 0.0, 1.0);
+    }
+";
+
+const BUILTIN_SHADERS: [(&str, &str); 5] = [
+    ("persistence", PERSISTENCE),
+    ("scanlines", SCANLINES),
+    ("bloom", BLOOM),
+    ("curvature", CURVATURE),
+    ("vignette", VIGNETTE),
+];
+
+const BUILTIN_PRESETS: [(&str, &str); 3] = [
+    ("none", ""),
+    (
+        "phosphor",
+        r#"
+        [[passes]]
+        shader = "persistence"
+        scale = 1
+        "#,
+    ),
+    (
+        "crt",
+        r#"
+        [[passes]]
+        shader = "persistence"
+        scale = 1
+        parameters = { decay = 0.7 }
+
+        [[passes]]
+        shader = "scanlines"
+
+        [[passes]]
+        shader = "bloom"
+
+        [[passes]]
+        shader = "curvature"
+        filter = "linear"
+
+        [[passes]]
+        shader = "vignette"
+        "#,
+    ),
+];
+
+/// Where user presets live, `shaders` next to the configuration file.
+pub fn presets_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("shaders"))
+}
+
+/// The built-in presets, then the ones in `presets_dir`, in the order they are cycled through.
+pub fn available() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN_PRESETS.iter().map(|(name, _)| name.to_string()).collect();
+
+    let mut files: Vec<String> = presets_dir()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .collect();
+    files.sort();
+
+    names.extend(files);
+    names
+}
+
+/// Loads a preset by name, built-in or from `presets_dir`, or from the path of a TOML file.
+pub fn load(name: &str) -> Result<Preset, String> {
+    if let Some((_, content)) = BUILTIN_PRESETS.iter().find(|(builtin, _)| *builtin == name) {
+        return parse(content, None);
+    }
+
+    let path = if name.ends_with(".toml") {
+        PathBuf::from(name)
+    } else {
+        presets_dir()
+            .map(|dir| dir.join(format!("{}.toml", name)))
+            .ok_or_else(|| format!("unknown shader preset `{}`", name))?
+    };
+    let content = fs::read_to_string(&path).map_err(|error| format!("{:?}: {}", path, error))?;
+    parse(&content, path.parent()).map_err(|error| format!("{:?}: {}", path, error))
+}
+
+fn parse(content: &str, dir: Option<&Path>) -> Result<Preset, String> {
+    let mut preset: Preset = toml::from_str(content).map_err(|error| error.to_string())?;
+
+    for pass in &mut preset.passes {
+        pass.source = match BUILTIN_SHADERS.iter().find(|(builtin, _)| *builtin == pass.shader) {
+            Some((_, source)) => source.to_string(),
+            None => {
+                let path = dir.unwrap_or_else(|| Path::new(".")).join(&pass.shader);
+                fs::read_to_string(&path).map_err(|error| format!("{:?}: {}", path, error))?
+            }
+        };
+    }
+    Ok(preset)
+}
+
+impl Preset {
+    /// The decay of the first persistence pass, for backends without shaders.
+    pub fn persistence(&self) -> Option<f32> {
+        self.passes
+            .iter()
+            .find(|pass| pass.shader == "persistence")
+            .map(|pass| pass.parameters.get("decay").copied().unwrap_or(0.8))
+    }
+}
+
+/// What the `persistence` shader does, on the CPU: every pixel fades from its
+/// previous value instead of going dark at once, so sprites erased and drawn
+/// again every frame don't flicker. Rounds like an 8-bit texture would.
+pub struct PersistenceFilter {
+    decay: f32,
+    history: Vec<u8>,
+}
+
+impl PersistenceFilter {
+    pub fn new(decay: f32) -> Self {
+        Self {
+            decay,
+            history: Vec::new(),
+        }
+    }
+
+    /// Filters RGBA pixels in place, keeping them for the next frame.
+    pub fn apply(&mut self, rgba: &mut [u8]) {
+        if self.history.len() != rgba.len() {
+            self.history = rgba.to_vec();
+            return;
+        }
+
+        for (pixel, previous) in rgba.iter_mut().zip(self.history.iter_mut()) {
+            let faded = (*previous as f32 * self.decay).round() as u8;
+            *pixel = (*pixel).max(faded);
+            *previous = *pixel;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::video::{Frame, SoftwareSink, VideoSink};
+
+    const PALETTE: [(u8, u8, u8); 2] = [(0, 0, 0), (0xFF, 0xFF, 0xFF)];
+
+    /// Presents a frame of one pixel, lit or not, returning its red channel.
+    fn present(sink: &mut SoftwareSink, lit: bool) -> u8 {
+        let frame = Frame {
+            width: 1,
+            height: 1,
+            pixels: &[lit as u8],
+            planes: 1,
+            intensity: None,
+            colors: None,
+            true_color: None,
+        };
+        sink.present(&frame, &PALETTE);
+        sink.rgba()[0]
+    }
+
+    #[test]
+    fn persistence_trails_fade_by_the_decay() {
+        let mut sink = SoftwareSink::with_filter(Some(PersistenceFilter::new(0.5)));
+        assert_eq!(present(&mut sink, true), 0xFF);
+        assert_eq!(present(&mut sink, false), 0x80);
+        assert_eq!(present(&mut sink, false), 0x40);
+        assert_eq!(present(&mut sink, false), 0x20);
+        assert_eq!(present(&mut sink, true), 0xFF, "lit pixels are bright again at once");
+    }
+
+    #[test]
+    fn presets_fade_like_their_persistence_pass() {
+        assert_eq!(load("none").unwrap().persistence(), None);
+        assert_eq!(load("crt").unwrap().persistence(), Some(0.7));
+
+        let decay = load("phosphor").unwrap().persistence().expect("phosphor has a persistence pass");
+        let mut sink = SoftwareSink::with_filter(Some(PersistenceFilter::new(decay)));
+        present(&mut sink, true);
+        let trail: Vec<u8> = (0..3).map(|_| present(&mut sink, false)).collect();
+        assert_eq!(trail, [0xCC, 0xA3, 0x82]);
+    }
+}
@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::graphics::Color;
+use crate::video::Frame;
+
+/// Frames per second of recordings, one per emulated frame.
+const FPS: u64 = 60;
+
+/// Records the presented frames at the native resolution, times `scale`,
+/// to an animated GIF or to a raw Y4M stream, told apart by the extension.
+pub struct Recorder {
+    path: PathBuf,
+    scale: usize,
+    encoder: Encoder,
+}
+
+enum Encoder {
+    Gif(GifEncoder),
+    Y4m(Y4mEncoder),
+}
+
+impl Recorder {
+    pub fn create(path: &Path, scale: u32) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let encoder = match extension.as_str() {
+            "gif" => Encoder::Gif(GifEncoder::new(path)?),
+            "y4m" => Encoder::Y4m(Y4mEncoder::new(path)?),
+            _ => return Err(format!("{:?}: can only record to .gif or .y4m", path)),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            scale: scale.max(1) as usize,
+            encoder,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, frame: &Frame, palette: &[Color]) -> Result<(), String> {
+        let scale = self.scale;
+        match &mut self.encoder {
+            Encoder::Gif(gif) => gif.record(frame, palette, scale),
+            Encoder::Y4m(y4m) => y4m.record(frame, palette, scale),
+        }
+        .map_err(|error| format!("{:?}: {}", self.path, error))
+    }
+
+    /// Writes what is still pending and closes the file.
+    pub fn finish(self) -> Result<(), String> {
+        let path = self.path;
+        match self.encoder {
+            Encoder::Gif(gif) => gif.finish(),
+            Encoder::Y4m(y4m) => y4m.finish(),
+        }
+        .map_err(|error| format!("{:?}: {}", path, error))
+    }
+}
+
+/// A new file name in the current directory, like `chip8-1700000000.gif`.
+pub fn default_path(extension: &str) -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    PathBuf::from(format!("chip8-{}.{}", now.as_secs(), extension))
+}
+
+/// Index of the colour of `palette` closest to `color`.
+fn nearest(palette: &[Color], (r, g, b): Color) -> u8 {
+    let distance = |&(pr, pg, pb): &Color| {
+        let (dr, dg, db) = (pr as i32 - r as i32, pg as i32 - g as i32, pb as i32 - b as i32);
+        dr * dr + dg * dg + db * db
+    };
+    (0..palette.len()).min_by_key(|&index| distance(&palette[index])).unwrap_or(0) as u8
+}
+
+/// Makes a frame `scale` times bigger, as one value per pixel.
+fn upscale<T: Copy>(frame: &Frame, scale: usize, pixel: impl Fn(usize, usize) -> T) -> Vec<T> {
+    let mut pixels = Vec::with_capacity(frame.width * frame.height * scale * scale);
+    for y in 0..frame.height * scale {
+        for x in 0..frame.width * scale {
+            pixels.push(pixel(x / scale, y / scale));
+        }
+    }
+    pixels
+}
+
+struct GifFrame {
+    pixels: Vec<u8>,
+    /// RGB triplets.
+    colors: Vec<u8>,
+    width: u16,
+    height: u16,
+}
+
+/// GIF frames are palette indices, so the pixels are stored as they are with the
+/// palette of the recording, or their own one if it changed since it started.
+/// Frames identical to the previous one only make it last longer.
+struct GifEncoder {
+    /// Until the encoder is created, with the size of the first frame.
+    file: Option<File>,
+    encoder: Option<gif::Encoder<BufWriter<File>>>,
+    palette: Vec<u8>,
+    /// The last frame, written once a different one comes.
+    pending: Option<GifFrame>,
+    /// Frames recorded, and those already written, to turn them into GIF delays.
+    frames: u64,
+    written: u64,
+}
+
+impl GifEncoder {
+    fn new(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|error| format!("{:?}: {}", path, error))?;
+        Ok(Self {
+            file: Some(file),
+            encoder: None,
+            palette: Vec::new(),
+            pending: None,
+            frames: 0,
+            written: 0,
+        })
+    }
+
+    fn record(&mut self, frame: &Frame, palette: &[Color], scale: usize) -> Result<(), String> {
+        let (pixels, palette) = match frame.colors.is_some() || frame.true_color.is_some() || frame.intensity.is_some() {
+            // colours chosen by the program or faded make their own palette, in the order they show up,
+            // past 256 of them the closest one is used
+            true => {
+                let mut used: Vec<Color> = Vec::new();
+                let pixels = upscale(frame, scale, |x, y| frame.color(x, y, palette))
+                    .into_iter()
+                    .map(|color| match used.iter().position(|used| *used == color) {
+                        Some(index) => index as u8,
+                        None if used.len() < 256 => {
+                            used.push(color);
+                            (used.len() - 1) as u8
+                        }
+                        None => nearest(&used, color),
+                    })
+                    .collect();
+                (pixels, used)
+            }
+            // only the colours the planes can reach
+            false => {
+                let palette = &palette[..(1 << frame.planes).min(palette.len())];
+                let last = palette.len() - 1;
+                let pixels = upscale(frame, scale, |x, y| (frame.pixel(x, y) as usize).min(last) as u8);
+                (pixels, palette.to_vec())
+            }
+        };
+        // GIF palettes have a power of two colours
+        let mut colors: Vec<u8> = palette.iter().flat_map(|(r, g, b)| [*r, *g, *b]).collect();
+        colors.resize(3 * palette.len().next_power_of_two().max(2), 0);
+        let gif_frame = GifFrame {
+            pixels,
+            colors,
+            width: (frame.width * scale) as u16,
+            height: (frame.height * scale) as u16,
+        };
+
+        let repeated = self.pending.as_ref().is_some_and(|pending| {
+            (&pending.pixels, &pending.colors, pending.width, pending.height)
+                == (&gif_frame.pixels, &gif_frame.colors, gif_frame.width, gif_frame.height)
+        });
+        if !repeated {
+            self.flush()?;
+            self.pending = Some(gif_frame);
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the pending frame, lasting from when it was first recorded until now.
+    fn flush(&mut self) -> Result<(), String> {
+        let GifFrame { pixels, colors, width, height } = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        if let Some(file) = self.file.take() {
+            let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &colors)
+                .map_err(|error| error.to_string())?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(|error| error.to_string())?;
+            self.encoder = Some(encoder);
+            self.palette = colors.clone();
+        }
+
+        // GIF delays are in hundredths of a second, rounding the end of every frame keeps them in sync
+        let centiseconds = |frames: u64| (frames * 100 + FPS / 2) / FPS;
+        let delay = centiseconds(self.frames) - centiseconds(self.written);
+        self.written = self.frames;
+
+        let palette = if colors != self.palette { Some(colors) } else { None };
+        let gif_frame = gif::Frame {
+            width,
+            height,
+            delay: delay.min(u16::MAX as u64) as u16,
+            buffer: pixels.into(),
+            palette,
+            ..Default::default()
+        };
+
+        let encoder = self.encoder.as_mut().expect("No GIF encoder!");
+        encoder.write_frame(&gif_frame).map_err(|error| error.to_string())
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        self.flush()?;
+        match self.encoder.take() {
+            Some(encoder) => encoder
+                .into_inner()
+                .map_err(|error| error.to_string())?
+                .flush()
+                .map_err(|error| error.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Uncompressed YUV 4:4:4 frames, for `ffmpeg` and friends to encode.
+struct Y4mEncoder {
+    out: BufWriter<File>,
+    size: Option<(usize, usize)>,
+}
+
+impl Y4mEncoder {
+    fn new(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|error| format!("{:?}: {}", path, error))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            size: None,
+        })
+    }
+
+    fn record(&mut self, frame: &Frame, palette: &[Color], scale: usize) -> Result<(), String> {
+        let size = (frame.width * scale, frame.height * scale);
+        match self.size {
+            None => {
+                let header = format!("YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n", size.0, size.1, FPS);
+                self.out.write_all(header.as_bytes()).map_err(|error| error.to_string())?;
+                self.size = Some(size);
+            }
+            // Y4M streams can't change resolution
+            Some(first) if first != size => return Ok(()),
+            Some(_) => (),
+        }
+
+        let pixels = upscale(frame, scale, |x, y| yuv(frame.color(x, y, palette)));
+        let mut data = Vec::with_capacity(pixels.len() * 3 + 6);
+        data.extend_from_slice(b"FRAME\n");
+        data.extend(pixels.iter().map(|(y, _, _)| *y));
+        data.extend(pixels.iter().map(|(_, u, _)| *u));
+        data.extend(pixels.iter().map(|(_, _, v)| *v));
+        self.out.write_all(&data).map_err(|error| error.to_string())
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        self.out.flush().map_err(|error| error.to_string())
+    }
+}
+
+/// BT.601 limited range, what Y4M readers expect by default.
+fn yuv((r, g, b): Color) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0;
+    let u = 128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0;
+    let v = 128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0;
+    (y.round() as u8, u.round() as u8, v.round() as u8)
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::{ColorConfig, Hints};
+use crate::cpu::Quirks;
+
+/// The database shipped with the emulator, in the format of the community
+/// CHIP-8 database (https://github.com/chip-8/chip-8-database): a list of
+/// programs, and an index from the SHA-1 of every known ROM into that list.
+const BUNDLED_PROGRAMS: &str = include_str!("../data/programs.json");
+const BUNDLED_HASHES: &str = include_str!("../data/sha1-hashes.json");
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    description: Option<String>,
+    release: Option<String>,
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    /// Background first, then one colour per combination of planes.
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// What the database knows about a ROM, ready to configure the emulator with.
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub platform: Option<String>,
+    pub hints: Hints,
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+}
+
+impl Database {
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_PROGRAMS, BUNDLED_HASHES).expect("Bundled ROM database is invalid!")
+    }
+
+    /// Loads a copy of the community database, a directory containing
+    /// `programs.json` and `sha1-hashes.json`.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let read = |name: &str| {
+            let path = dir.join(name);
+            fs::read_to_string(&path).map_err(|error| format!("{:?}: {}", path, error))
+        };
+        Self::parse(&read("programs.json")?, &read("sha1-hashes.json")?)
+    }
+
+    fn parse(programs: &str, hashes: &str) -> Result<Self, String> {
+        Ok(Self {
+            programs: serde_json::from_str(programs).map_err(|error| error.to_string())?,
+            hashes: serde_json::from_str(hashes).map_err(|error| error.to_string())?,
+        })
+    }
+
+    pub fn lookup(&self, hash: &str) -> Option<RomInfo> {
+        let program = self.programs.get(*self.hashes.get(hash)?)?;
+        let rom = program.roms.get(hash)?;
+
+        let platform = rom.platforms.first().cloned();
+        let quirks = platform.as_ref().and_then(|platform| {
+            let mut quirks = platform_quirks(platform)?;
+            if let Some(overrides) = rom.quirky_platforms.get(platform) {
+                apply_overrides(&mut quirks, overrides);
+            }
+            Some(quirks)
+        });
+
+        let colors = rom
+            .colors
+            .as_ref()
+            .filter(|colors| colors.pixels.len() >= 2)
+            .map(|colors| ColorConfig::from_colors(&colors.pixels));
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            description: program.description.clone(),
+            release: program.release.clone(),
+            platform,
+            hints: Hints {
+                speed: rom.tickrate,
+                quirks,
+                colors,
+                keys: rom.keys.clone(),
+            },
+        })
+    }
+}
+
+/// Quirks of the platforms the database knows about, see its `platforms.json`.
+fn platform_quirks(platform: &str) -> Option<Quirks> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Quirks::preset("vip"),
+        "modernChip8" => Some(Quirks {
+            shift_vy: true,
+            load_store_increment: true,
+            jump_vx: false,
+            vf_reset: false,
+            clip_sprites: true,
+            vf_row_count: false,
+            key_release: true,
+        }),
+        "xochip" => Quirks::preset("xochip"),
+        "chip48" | "superchip1" | "superchip" => Quirks::preset("schip"),
+        _ => None,
+    }
+}
+
+/// Applies the database quirk names, where `true` means the quirky behaviour.
+fn apply_overrides(quirks: &mut Quirks, overrides: &HashMap<String, bool>) {
+    for (name, enabled) in overrides {
+        match name.as_str() {
+            "shift" => quirks.shift_vy = !enabled,
+            "memoryLeaveIUnchanged" => quirks.load_store_increment = !enabled,
+            "jump" => quirks.jump_vx = *enabled,
+            "logic" => quirks.vf_reset = *enabled,
+            "wrap" => quirks.clip_sprites = !enabled,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Plain",
+            "roms": { "aaaa": { "platforms": ["originalChip8"] } }
+        },
+        {
+            "title": "Quirky",
+            "authors": ["Someone"],
+            "roms": {
+                "bbbb": {
+                    "platforms": ["modernChip8", "xochip"],
+                    "quirkyPlatforms": {
+                        "modernChip8": { "shift": true, "memoryLeaveIUnchanged": true, "wrap": true, "jump": true, "logic": true }
+                    },
+                    "tickrate": 30,
+                    "colors": { "pixels": ["#112233", "#445566", "#778899", "#AABBCC"] },
+                    "keys": { "up": 5 }
+                }
+            }
+        }
+    ]"##;
+    const HASHES: &str = r#"{ "aaaa": 0, "bbbb": 1 }"#;
+
+    #[test]
+    fn lookup_translates_quirks_overrides_and_colours() {
+        let database = Database::parse(PROGRAMS, HASHES).unwrap();
+        assert!(database.lookup("cccc").is_none());
+
+        let plain = database.lookup("aaaa").unwrap();
+        assert_eq!(plain.title, "Plain");
+        assert_eq!(plain.hints.quirks, Quirks::preset("vip"));
+        assert!(plain.hints.speed.is_none() && plain.hints.colors.is_none());
+
+        let quirky = database.lookup("bbbb").unwrap();
+        assert_eq!(quirky.title, "Quirky");
+        assert_eq!(quirky.authors, ["Someone"]);
+        assert_eq!(quirky.platform.as_deref(), Some("modernChip8"));
+        assert_eq!(quirky.hints.speed, Some(30));
+        assert_eq!(quirky.hints.keys.get("up"), Some(&5));
+        // `true` in the database is the quirky behaviour, which is the opposite
+        // of `shift_vy`, `load_store_increment` and `clip_sprites`.
+        let quirks = quirky.hints.quirks.unwrap();
+        assert!(!quirks.shift_vy && !quirks.load_store_increment && !quirks.clip_sprites);
+        assert!(quirks.jump_vx && quirks.vf_reset);
+        assert!(!quirks.vf_row_count && quirks.key_release, "untouched quirks keep the platform's");
+
+        let colors = quirky.hints.colors.unwrap();
+        assert_eq!(colors.background.as_deref(), Some("112233"));
+        assert_eq!(colors.foreground.as_deref(), Some("445566"));
+        assert_eq!(colors.foreground2.as_deref(), Some("778899"));
+        assert_eq!(colors.blend.as_deref(), Some("AABBCC"));
+    }
+}
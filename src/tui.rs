@@ -0,0 +1,227 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, terminal};
+
+use crate::config::Settings;
+use crate::cpu::Registers;
+use crate::disasm;
+use crate::hotkeys::{self, Hotkey};
+use crate::machine::Machine;
+use crate::graphics::Color;
+use crate::video::{self, Frame, VideoSink};
+
+/// Terminals repeat a held key only after a delay, so a key stays down
+/// long enough after its first press for the repetitions to kick in...
+const FIRST_RELEASE: Duration = Duration::from_millis(500);
+/// ...and only a little while after every repetition.
+const REPEAT_RELEASE: Duration = Duration::from_millis(100);
+
+/// Runs the emulator inside the terminal, for machines without a display.
+/// The screen is drawn with half blocks, with the registers on its right.
+///
+/// Most terminals only report key presses, so a keypad key is released when
+/// its key stops repeating. Terminals speaking the kitty keyboard protocol
+/// report releases too, and those are used instead.
+pub struct Tui {
+    /// When every keypad key will be released, if it is down.
+    /// Without release timeouts, only tells whether it is down.
+    keys: [Option<Instant>; 16],
+    release_events: bool,
+    registers: Registers,
+    next_opcode: u16,
+    turbo: bool,
+    status: String,
+    /// The last `message`, printing it would be wiped with the next frame.
+    message: String,
+}
+
+impl Tui {
+    pub fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if release_events {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        Ok(Self {
+            keys: [None; 16],
+            release_events,
+            registers: Registers::default(),
+            next_opcode: 0,
+            turbo: false,
+            status: String::new(),
+            message: String::new(),
+        })
+    }
+
+    /// Handles the pending key events and lets go of the keys that stopped repeating.
+    /// Returns the hotkeys pressed, Ctrl+C quits like Esc.
+    pub fn poll(&mut self, machine: &mut dyn Machine, settings: &Settings) -> Vec<Hotkey> {
+        let now = Instant::now();
+        let mut hotkeys = Vec::new();
+
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            let key = match event::read() {
+                Ok(Event::Key(key)) => key,
+                _ => continue,
+            };
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                hotkeys.push(Hotkey::Quit);
+                continue;
+            }
+
+            let name = match key_name(key.code) {
+                Some(name) => name,
+                None => continue,
+            };
+            if let Some(hotkey) = hotkeys::hotkey(&name, key.kind != KeyEventKind::Release) {
+                match hotkey {
+                    // without releases, the turbo key toggles
+                    Hotkey::Turbo(_) if !self.release_events => {
+                        self.turbo = !self.turbo;
+                        hotkeys.push(Hotkey::Turbo(self.turbo));
+                    }
+                    _ if key.kind == KeyEventKind::Repeat => (),
+                    _ => hotkeys.push(hotkey),
+                }
+                continue;
+            }
+            // terminals can't tell the numeric keypad apart, so there's no second keypad
+            let keypad = match settings.keypad_key(&name) {
+                Some(keypad) => keypad as usize,
+                None => continue,
+            };
+            self.keys[keypad] = match key.kind {
+                KeyEventKind::Release => None,
+                // held until released
+                _ if self.release_events => Some(now),
+                _ if self.keys[keypad].is_some() => Some(now + REPEAT_RELEASE),
+                _ => Some(now + FIRST_RELEASE),
+            };
+        }
+
+        for (keypad, release) in self.keys.iter_mut().enumerate() {
+            if !self.release_events && release.is_some_and(|release| release <= now) {
+                *release = None;
+            }
+            machine.set_key(keypad as u8, release.is_some() as u8);
+        }
+
+        self.registers = machine.registers();
+        self.next_opcode = machine.next_opcode();
+        hotkeys
+    }
+
+    fn pane(&self) -> Vec<String> {
+        let registers = &self.registers;
+        let mut pane = vec![
+            format!("PC {:04X}  I {:04X}  SP {:X}", registers.pc, registers.i, registers.sp),
+            format!("DT {:02X}    ST {:02X}", registers.delay_timer, registers.sound_timer),
+            String::new(),
+        ];
+        for (row, values) in registers.v.chunks(4).enumerate() {
+            let line: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value))
+                .collect();
+            pane.push(line.join("  "));
+        }
+        pane.push(String::new());
+        pane.push(format!("{:04X}  {}", registers.pc, disasm::disassemble(self.next_opcode)));
+        if let Some(x) = registers.waiting_for_key {
+            pane.push(format!("Waiting for a key in V{:X}", x));
+        }
+        pane.push(String::new());
+
+        let held: Vec<String> = (0..16)
+            .filter(|keypad| self.keys[*keypad].is_some())
+            .map(|keypad| format!("{:X}", keypad))
+            .collect();
+        pane.push(format!("Keys {}", held.join(" ")));
+        pane.push(self.status.clone());
+        pane.push(self.message.clone());
+        pane.push("Esc quits, F5 pause, F6 step, F7 slow".to_string());
+        pane.push("Tab turbo, PgUp/PgDn speed, F8 palette".to_string());
+        pane.push("F12 record, F2 screenshot".to_string());
+        pane
+    }
+}
+
+impl VideoSink for Tui {
+    fn present(&mut self, frame: &Frame, palette: &[Color]) {
+        let screen = video::half_blocks(frame, palette);
+        let pane = self.pane();
+
+        let mut text = String::from("\x1b[H");
+        for row in 0..screen.len().max(pane.len()) {
+            text.push_str(screen.get(row).map(String::as_str).unwrap_or(""));
+            text.push_str("  ");
+            text.push_str(pane.get(row).map(String::as_str).unwrap_or(""));
+            text.push_str("\x1b[K\r\n");
+        }
+        // wipes anything printed below
+        text.push_str("\x1b[J");
+
+        let mut out = io::stdout();
+        // a closed terminal isn't worth stopping the emulation for
+        let _ = out.write_all(text.as_bytes()).and_then(|_| out.flush());
+    }
+
+    fn status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+
+    fn message(&mut self, message: &str) {
+        self.message = message.to_string();
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.release_events {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        // the alternate screen is gone, and the reason the emulation stopped with it
+        if !self.message.is_empty() {
+            eprintln!("{}", self.message);
+        }
+    }
+}
+
+/// The name the window would give the same key, so the keyboard section
+/// of the configuration works in both.
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(c) if c.is_ascii_digit() => format!("Key{}", c),
+        KeyCode::Char(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase().to_string(),
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Enter => "Return".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Backspace => "Back".to_string(),
+        KeyCode::Esc => "Escape".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        _ => return None,
+    };
+    Some(name)
+}
@@ -0,0 +1,228 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::graphics::Color;
+use crate::postfx::PersistenceFilter;
+
+/// One frame of the emulated display.
+/// Every pixel is a byte used as an index into a palette, background first.
+pub struct Frame<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [u8],
+    /// Bit planes making up `pixels`, which index the first `1 << planes` colours of the palette.
+    /// 2 once an XO-CHIP program selects its second plane, 1 otherwise.
+    pub planes: u8,
+    /// How bright every pixel is, from the background (0) to its colour (255).
+    /// Fully bright when missing.
+    pub intensity: Option<&'a [u8]>,
+    /// The colours the program chose, instead of the palette.
+    pub colors: Option<ColorMap<'a>>,
+    /// The colour of every pixel, for machines drawing in colour like MegaChip.
+    /// Used instead of `pixels` and the palette.
+    pub true_color: Option<&'a [Color]>,
+}
+
+/// Colours of machines with a colour board, like CHIP-8X.
+pub struct ColorMap<'a> {
+    pub background: Color,
+    /// Indices into `colors` for the lit pixels, in zones 8 columns wide
+    /// and as many rows as fit, stretched over the display.
+    pub zones: &'a [u8],
+    pub colors: &'a [Color],
+}
+
+impl Frame<'_> {
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// The colour of a pixel, falling back to the last colour of short palettes.
+    pub fn color(&self, x: usize, y: usize, palette: &[Color]) -> Color {
+        if let Some(colors) = self.true_color {
+            return colors[y * self.width + x];
+        }
+        let index = self.pixel(x, y) as usize;
+        let (background, color) = match &self.colors {
+            Some(map) => {
+                let rows = map.zones.len() / 8;
+                let zone = map.zones[y * rows / self.height * 8 + x * 8 / self.width];
+                let color = if index == 0 { map.background } else { map.colors[zone as usize] };
+                (map.background, color)
+            }
+            None => (palette[0], palette[index.min(palette.len() - 1)]),
+        };
+        match self.intensity {
+            Some(intensity) => blend(background, color, intensity[y * self.width + x]),
+            None => color,
+        }
+    }
+
+    /// Writes the frame to a PNG file, every pixel `scale` times bigger,
+    /// with `text` as `tEXt` chunks: keywords and their values.
+    pub fn save_png(&self, path: &Path, palette: &[Color], scale: u32, text: &[(&str, String)]) -> Result<(), String> {
+        let scale = scale.max(1) as usize;
+        let mut rgba = Vec::with_capacity(self.width * self.height * scale * scale * 4);
+        for y in 0..self.height * scale {
+            for x in 0..self.width * scale {
+                let (r, g, b) = self.color(x / scale, y / scale, palette);
+                rgba.extend_from_slice(&[r, g, b, 0xFF]);
+            }
+        }
+        write_png(path, self.width * scale, self.height * scale, &rgba, text)
+            .map_err(|error| format!("{:?}: {}", path, error))
+    }
+}
+
+/// Mixes `to` into `from`, by `amount` out of 255.
+fn blend(from: Color, to: Color, amount: u8) -> Color {
+    let mix = |from: u8, to: u8| (from as i32 + (to as i32 - from as i32) * amount as i32 / 255) as u8;
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+/// Something that shows (or stores) the emulated display, given every frame.
+pub trait VideoSink {
+    fn present(&mut self, frame: &Frame, palette: &[Color]);
+
+    /// A line about the emulation, like its speed, for the sinks that can show it.
+    fn status(&mut self, _status: &str) {}
+
+    /// Something that happened, like a break or a saved screenshot,
+    /// printed to stderr unless the sink shows it.
+    fn message(&mut self, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+/// Renders to an RGBA buffer in memory, at the native resolution.
+pub struct SoftwareSink {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    filter: Option<PersistenceFilter>,
+}
+
+impl SoftwareSink {
+    pub fn new() -> Self {
+        Self::with_filter(None)
+    }
+
+    /// Runs every frame through the CPU version of the persistence shader.
+    pub fn with_filter(filter: Option<PersistenceFilter>) -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            rgba: Vec::new(),
+            filter,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// 4 bytes per pixel, row after row.
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+}
+
+impl VideoSink for SoftwareSink {
+    fn present(&mut self, frame: &Frame, palette: &[Color]) {
+        self.width = frame.width;
+        self.height = frame.height;
+        self.rgba.clear();
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let (r, g, b) = frame.color(x, y, palette);
+                self.rgba.extend_from_slice(&[r, g, b, 0xFF]);
+            }
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.apply(&mut self.rgba);
+        }
+    }
+}
+
+/// Writes every frame to a numbered PNG file inside a directory.
+pub struct PngSink {
+    dir: PathBuf,
+    count: u64,
+    software: SoftwareSink,
+}
+
+impl PngSink {
+    pub fn new(dir: PathBuf, filter: Option<PersistenceFilter>) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            count: 0,
+            software: SoftwareSink::with_filter(filter),
+        })
+    }
+
+    fn write(&self, path: &Path) -> Result<(), png::EncodingError> {
+        write_png(path, self.software.width(), self.software.height(), self.software.rgba(), &[])
+    }
+}
+
+fn write_png(path: &Path, width: usize, height: usize, rgba: &[u8], text: &[(&str, String)]) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, value) in text {
+        encoder.add_text_chunk(keyword.to_string(), value.clone())?;
+    }
+    encoder.write_header()?.write_image_data(rgba)
+}
+
+impl VideoSink for PngSink {
+    fn present(&mut self, frame: &Frame, palette: &[Color]) {
+        self.software.present(frame, palette);
+
+        let path = self.dir.join(format!("frame_{:06}.png", self.count));
+        if let Err(error) = self.write(&path) {
+            eprintln!("Couldn't write {:?}: {}", path, error);
+        }
+        self.count += 1;
+    }
+}
+
+/// Draws the display for a terminal with ANSI colours, two pixels per character:
+/// the upper half block takes the foreground colour of the upper pixel
+/// and the background colour of the lower one.
+pub fn half_blocks(frame: &Frame, palette: &[Color]) -> Vec<String> {
+    (0..frame.height)
+        .step_by(2)
+        .map(|y| {
+            let mut line = String::new();
+            for x in 0..frame.width {
+                let (r, g, b) = frame.color(x, y, palette);
+                let (lr, lg, lb) = if y + 1 < frame.height {
+                    frame.color(x, y + 1, palette)
+                } else {
+                    palette[0]
+                };
+                line.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀",
+                    r, g, b, lr, lg, lb
+                ));
+            }
+            line.push_str("\x1b[0m");
+            line
+        })
+        .collect()
+}
+
+/// Discards every frame, for runs where only the end result matters.
+pub struct NullSink;
+
+impl VideoSink for NullSink {
+    fn present(&mut self, _frame: &Frame, _palette: &[Color]) {}
+}
@@ -0,0 +1,292 @@
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::cpu::{self, Registers};
+use crate::machine::{Halt, Machine};
+use crate::video::Frame;
+
+/// 4K of RAM, the most the VIP took on its board.
+const RAM_SIZE: usize = 0x1000;
+/// The monitor ROM and the CHIP-8 interpreter are 512 bytes each.
+const ROM_SIZE: usize = 0x200;
+/// Where CHIP-8 programs start, right after the interpreter...
+const PROGRAM_START: usize = 0x200;
+/// ...and where they must end, the interpreter keeps its stack and variables above.
+const PROGRAM_END: usize = 0xE90;
+/// The CHIP-8 registers and stack, in the page below the display (the last page of the RAM).
+const VARIABLES: usize = RAM_SIZE - 0x110;
+const STACK: u16 = (RAM_SIZE - 0x131) as u16;
+
+/// The CDP1861 draws 262 lines of 14 machine cycles every frame.
+const LINE_CYCLES: u32 = 14;
+const FRAME_CYCLES: u32 = 262 * LINE_CYCLES;
+/// 128 lines of the picture, fetched by DMA 8 bytes at a time,
+/// with every CHIP-8 row repeated on 4 of them by the interpreter.
+const DISPLAY_LINES: std::ops::Range<u32> = 80..208;
+/// The interrupt comes 29 machine cycles before the first line of the picture,
+/// just enough for the interrupt routine to get ready.
+const INTERRUPT_CYCLE: u32 = DISPLAY_LINES.start * LINE_CYCLES - 29;
+/// `EF1` tells the 4 lines before the picture and its last 4 lines.
+const EF1_LINES: [std::ops::Range<u32>; 2] = [76..80, 204..208];
+
+/// **RCA COSMAC VIP**: a CDP1802, its RAM, the CDP1861 video chip and the
+/// hex keypad, running the original 512-byte CHIP-8 interpreter loaded at
+/// `0x000`, which users have to supply. The display wait, the timing of
+/// every instruction and the bugs of the interpreter all come for free.
+///
+/// The interpreter waits for keys (`FX0A`) with a routine of the monitor ROM,
+/// so it needs the monitor too, which is run from the reset like on the VIP.
+/// Without it, the VIP starts as the monitor leaves it for the interpreter.
+pub struct Vip {
+    cpu: Cdp1802,
+    hardware: Hardware,
+    /// Machine cycles the last instruction of a frame took from the next one.
+    overrun: u32,
+    /// The bytes of the picture, as fetched on every line.
+    lines: [[u8; 8]; (DISPLAY_LINES.end - DISPLAY_LINES.start) as usize],
+    output: [u8; 64 * 32],
+    rom_hash: String,
+}
+
+/// Everything on the bus of the CPU.
+struct Hardware {
+    ram: [u8; RAM_SIZE],
+    monitor: Option<Vec<u8>>,
+    /// After a reset the monitor shows at `0x0000` too, until an address with A15 is read.
+    monitor_shadow: bool,
+    keys: [bool; 16],
+    /// The key `EF3` tells about, set with `OUT 2`.
+    keypad_latch: u8,
+    display_on: bool,
+    ef1: bool,
+}
+
+impl Bus for Hardware {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x8000 != 0 {
+            self.monitor_shadow = false;
+        }
+        if address & 0x8000 != 0 || self.monitor_shadow {
+            return self.monitor.as_ref().map_or(0, |monitor| monitor[address as usize % ROM_SIZE]);
+        }
+        // the RAM shows again and again up to 0x7FFF
+        self.ram[address as usize % RAM_SIZE]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x8000 == 0 {
+            self.ram[address as usize % RAM_SIZE] = value;
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.keypad_latch = value & 0xF,
+            _ => (),
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        match flag {
+            1 => self.ef1,
+            3 => self.keys[self.keypad_latch as usize],
+            _ => false,
+        }
+    }
+}
+
+impl Vip {
+    /// `monitor` is the ROM at `0x8000`, `program` the CHIP-8 program.
+    pub fn new(interpreter: &[u8], monitor: Option<Vec<u8>>, program: &[u8]) -> Result<Self, String> {
+        if interpreter.len() > ROM_SIZE {
+            return Err(format!("the interpreter is {} bytes, more than {}", interpreter.len(), ROM_SIZE));
+        }
+        if let Some(monitor) = &monitor {
+            if monitor.len() != ROM_SIZE {
+                return Err(format!("the monitor is {} bytes instead of {}", monitor.len(), ROM_SIZE));
+            }
+        }
+        if program.len() > PROGRAM_END - PROGRAM_START {
+            return Err(format!(
+                "the program is {} bytes, more than the {} the VIP has room for",
+                program.len(),
+                PROGRAM_END - PROGRAM_START
+            ));
+        }
+
+        let mut ram = [0; RAM_SIZE];
+        ram[..interpreter.len()].copy_from_slice(interpreter);
+        ram[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+
+        let mut cpu = Cdp1802::new();
+        if monitor.is_none() {
+            // the monitor leaves the last page of the RAM in R1.1 and runs 0x0000 with R0
+            cpu.r[1] = ((RAM_SIZE / 0x100 - 1) << 8) as u16;
+        }
+
+        Ok(Self {
+            cpu,
+            hardware: Hardware {
+                ram,
+                monitor_shadow: monitor.is_some(),
+                monitor,
+                keys: [false; 16],
+                keypad_latch: 0,
+                display_on: false,
+                ef1: false,
+            },
+            overrun: 0,
+            lines: [[0; 8]; (DISPLAY_LINES.end - DISPLAY_LINES.start) as usize],
+            output: [0; 64 * 32],
+            rom_hash: cpu::hash_program(program),
+        })
+    }
+
+    fn read(&self, address: usize) -> u8 {
+        self.hardware.ram[address % RAM_SIZE]
+    }
+}
+
+impl Machine for Vip {
+    /// Runs the 3668 machine cycles of a frame, whatever the speed.
+    /// DMA takes 8 of the 14 cycles of every line of the picture,
+    /// only leaving the CPU time for 3 instructions on those.
+    fn emulate_frame(&mut self, _speed: u32) -> Result<(), Halt> {
+        let mut cycle = self.overrun;
+        let mut interrupted = false;
+        let mut fetched = None;
+
+        while cycle < FRAME_CYCLES {
+            let line = cycle / LINE_CYCLES;
+            let display_on = self.hardware.display_on;
+            self.hardware.ef1 = display_on && EF1_LINES.iter().any(|lines| lines.contains(&line));
+
+            // the interrupt is held for 2 lines, until the CPU takes it
+            let interrupt = (INTERRUPT_CYCLE..DISPLAY_LINES.start * LINE_CYCLES).contains(&cycle);
+            if display_on && interrupt && !interrupted && self.cpu.interrupt() {
+                interrupted = true;
+                cycle += 1;
+                continue;
+            }
+
+            if display_on && DISPLAY_LINES.contains(&line) && fetched != Some(line) {
+                let row = &mut self.lines[(line - DISPLAY_LINES.start) as usize];
+                for byte in row.iter_mut() {
+                    *byte = self.cpu.dma_out(&mut self.hardware);
+                }
+                fetched = Some(line);
+                cycle += 8;
+                continue;
+            }
+
+            cycle += self.cpu.step(&mut self.hardware);
+        }
+        self.overrun = cycle - FRAME_CYCLES;
+
+        for (n, pixel) in self.output.iter_mut().enumerate() {
+            let (x, y) = (n % 64, n / 64);
+            // the second of the 4 lines of every row, in case the interpreter is a line off
+            let byte = self.lines[y * 4 + 1][x / 8];
+            *pixel = (self.hardware.display_on && byte & (0x80 >> (x % 8)) != 0) as u8;
+        }
+        Ok(())
+    }
+
+    fn frame(&self) -> Frame<'_> {
+        Frame {
+            width: 64,
+            height: 32,
+            pixels: &self.output,
+            planes: 1,
+            intensity: None,
+            colors: None,
+            true_color: None,
+        }
+    }
+
+    /// The VIP only has the one keypad.
+    fn set_key(&mut self, key: u8, state: u8) {
+        if let Some(key) = self.hardware.keys.get_mut(key as usize) {
+            *key = state != 0;
+        }
+    }
+
+    /// The speaker plays while Q is set.
+    fn is_beeping(&self) -> bool {
+        self.cpu.q
+    }
+
+    /// The interpreter keeps the CHIP-8 program counter in R5, I in RA,
+    /// the timers in R8 and its stack pointer in R2.
+    fn registers(&self) -> Registers {
+        let mut v = [0; 16];
+        for (n, register) in v.iter_mut().enumerate() {
+            *register = self.read(VARIABLES + n);
+        }
+        Registers {
+            v,
+            i: self.cpu.r[0xA] as u32,
+            pc: self.cpu.r[5],
+            sp: (STACK.saturating_sub(self.cpu.r[2]) / 2) as u8,
+            delay_timer: (self.cpu.r[8] >> 8) as u8,
+            sound_timer: self.cpu.r[8] as u8,
+            // the interpreter waits in a routine of the monitor, which keeps no trace of it
+            waiting_for_key: None,
+        }
+    }
+
+    fn next_opcode(&self) -> u16 {
+        let pc = self.cpu.r[5] as usize;
+        (self.read(pc) as u16) << 8 | self.read(pc + 1) as u16
+    }
+
+    fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Chip8;
+
+    /// Just enough of an interpreter for `6XKK`, `7XKK` and `1NNN`, keeping the
+    /// program counter in R5 and V0 to VF where the real one does.
+    #[rustfmt::skip]
+    const INTERPRETER: [u8; 0x38] = [
+        0xF8, 0x02, 0xB5, 0xF8, 0x00, 0xA5, // R5 := 0x200
+        0xF8, 0x0E, 0xB6,                   // R6.1 := page of the variables
+        // fetch: RF.0 := high byte, R6 := &Vx, then dispatch on the high nibble
+        0x45, 0xAF, 0xFA, 0x0F, 0xFC, 0xF0, 0xA6, 0x8F, 0xFA, 0xF0,
+        0xFB, 0x60, 0x32, 0x20,             // 6XKK
+        0xFB, 0x10, 0x32, 0x28,             // 7XKK
+        0xFB, 0x60, 0x32, 0x30,             // 1NNN
+        0x00,                               // anything else stops
+        0x45, 0x56, 0x30, 0x09, 0x00, 0x00, 0x00, 0x00, // Vx := kk
+        0xE6, 0x45, 0xF4, 0x56, 0x30, 0x09, 0x00, 0x00, // Vx := Vx + kk
+        0x05, 0xA5, 0x8F, 0xFA, 0x0F, 0xB5, 0x30, 0x09, // PC := NNN
+    ];
+
+    #[test]
+    fn runs_programs_like_the_chip8_core() {
+        // v0 := 5 + 0xFE, v1 := 0x10 + 1, v2 := 0xAA, loop
+        let program = [0x60, 0x05, 0x70, 0xFE, 0x61, 0x10, 0x71, 0x01, 0x62, 0xAA, 0x12, 0x0A];
+        let mut vip = Vip::new(&INTERPRETER, None, &program).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.initialize(0x200);
+        chip8.load_program(program.to_vec()).unwrap();
+
+        vip.emulate_frame(0).unwrap();
+        chip8.emulate_frame(10).unwrap();
+        assert_eq!(vip.registers().v, chip8.registers().v);
+        assert_eq!(&vip.registers().v[..3], [0x03, 0x11, 0xAA]);
+        // the jump back, or halfway through fetching it
+        assert!((0x20A..=0x20B).contains(&vip.registers().pc));
+    }
+}